@@ -1,34 +1,86 @@
-use madrid_cita_previa::AppointmentSession;
-use reqwest::ClientBuilder;
+use madrid_cita_previa::{AttentionType, ProcedureId};
+use serde::Serialize;
 
-use super::ExitCode;
+use super::{ExitCode, GlobalArgs};
 
 #[derive(clap::Args)]
 pub struct Args {
     /// Procedure ID to find appointments for
     #[arg(short, long)]
     pub procedure_id: u32,
+
+    /// Prints the result in JSON format, or null if no office has
+    /// appointments
+    #[arg(long)]
+    pub json: bool,
 }
 
-pub async fn main(args: Args) -> anyhow::Result<ExitCode> {
-    let Some(procedure) = madrid_cita_previa_data::procedures::ALL
-        .iter()
-        .find(|proc| proc.procedure_id.0 == args.procedure_id)
-    else {
+#[derive(Serialize)]
+pub struct ClosestAppointmentInfo {
+    office_id: u32,
+    office_name: String,
+    address: String,
+    district: String,
+
+    // Null if no day with available slots was found in the office.
+    date: Option<String>,
+
+    // Timestamps of the available slots of the date.
+    slots: Vec<i64>,
+}
+
+pub async fn main(args: Args, global: &GlobalArgs) -> anyhow::Result<ExitCode> {
+    let model = global.load_model()?;
+    let Some(procedure) = model.procedure(ProcedureId(args.procedure_id)) else {
         eprintln!("Unknown procedure: {}", args.procedure_id);
         return Ok(ExitCode::FaultOrArgsError);
     };
 
     eprintln!("Selected procedure: {}", procedure.procedure_name);
-    let sess = AppointmentSession::new(ClientBuilder::new());
-    let office = sess
-        .get_office_closest_appointment(procedure.procedure_id)
-        .await?;
+    let sess = global.build_session()?;
+    let Some(closest) = sess
+        .get_closest_appointment(procedure.procedure_id, AttentionType::default())
+        .await?
+    else {
+        if args.json {
+            // Still valid JSON, so scripts reading the output can parse it.
+            println!("null");
+        } else {
+            eprintln!("No office has appointments for this procedure.");
+        }
+        return Ok(ExitCode::RequestUnsatisfied);
+    };
 
-    if let Some(office) = office {
-        println!("{}", office.name);
+    if args.json {
+        let info = ClosestAppointmentInfo {
+            office_id: closest.office.office_id,
+            office_name: closest.office.name.clone(),
+            address: closest.office.address.clone(),
+            district: closest.office.district_name.clone(),
+            date: closest.date.map(|date| date.to_string()),
+            slots: closest
+                .slots
+                .iter()
+                .map(|slot| slot.time.timestamp())
+                .collect(),
+        };
+        println!("{}", serde_json::to_string(&info).unwrap());
     } else {
-        return Ok(ExitCode::RequestUnsatisfied);
+        println!("Office: {}", closest.office.name);
+        println!("Address: {}", closest.office.address);
+        println!("District: {}", closest.office.district_name);
+        match (closest.date, closest.first_slot()) {
+            (Some(date), Some(slot)) => {
+                println!("Date: {}", date);
+                println!("First slot: {}", slot.time.format("%H:%M"));
+            }
+            _ => println!("Date: no available slots left"),
+        }
+    }
+
+    if closest.date.is_some() {
+        Ok(ExitCode::Ok)
+    } else {
+        Ok(ExitCode::RequestUnsatisfied)
     }
-    Ok(ExitCode::Ok)
 }
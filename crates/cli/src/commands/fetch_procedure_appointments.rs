@@ -1,10 +1,12 @@
-use chrono::{DateTime, NaiveDate};
-use madrid_cita_previa::{AppointmentSession, StaticOffice};
-use reqwest::ClientBuilder;
+use chrono::{NaiveDate, NaiveTime};
+use futures_util::{StreamExt, stream};
+use madrid_cita_previa::{
+    AvailabilityQueryOptions, Coordinates, DataGenOffice, DateRange, OfficeQuery, ProcedureId,
+    TimeWindow,
+};
 use serde::Serialize;
-use std::ops::Deref;
 
-use super::ExitCode;
+use super::{ExitCode, GlobalArgs};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -24,9 +26,75 @@ pub struct Args {
     #[arg(short = 'g', long)]
     office_group: Option<String>,
 
+    /// Search only in the offices within --radius kilometers of this location,
+    /// given as LATITUDE,LONGITUDE
+    #[arg(long, requires = "radius", value_parser = parse_coordinates, allow_hyphen_values = true)]
+    near: Option<Coordinates>,
+
+    /// Maximum distance in kilometers to the location given by --near
+    #[arg(long, requires = "near")]
+    radius: Option<f64>,
+
     /// Prints all the results at once in JSON format
-    #[arg(long)]
+    #[arg(long, conflicts_with = "ndjson")]
     json: bool,
+
+    /// Prints the results of each office in JSON format as soon as they are
+    /// available, one per line
+    #[arg(long)]
+    ndjson: bool,
+
+    /// Maximum number of offices queried at the same time
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Ignore appointments before this day (YYYY-MM-DD)
+    #[arg(long)]
+    from: Option<NaiveDate>,
+
+    /// Ignore appointments after this day (YYYY-MM-DD)
+    #[arg(long, conflicts_with = "within_days")]
+    to: Option<NaiveDate>,
+
+    /// Ignore appointments later than this number of days from today
+    #[arg(long)]
+    within_days: Option<u64>,
+
+    /// Only report slots starting within these hours, as in 9-14 or
+    /// 9:30-14:00. Requires --slots
+    #[arg(long, requires = "slots", value_parser = parse_time_window)]
+    between_hours: Option<TimeWindow>,
+}
+
+fn parse_coordinates(value: &str) -> Result<Coordinates, String> {
+    let Some((latitude, longitude)) = value.split_once(',') else {
+        return Err("Expected LATITUDE,LONGITUDE".to_string());
+    };
+    let parse = |degrees: &str| {
+        degrees
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid coordinate: {}", degrees))
+    };
+    Ok(Coordinates::new(parse(latitude)?, parse(longitude)?))
+}
+
+fn parse_time_window(value: &str) -> Result<TimeWindow, String> {
+    let parse_time = |time: &str| {
+        let time = time.trim();
+        NaiveTime::parse_from_str(time, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(&format!("{}:00", time), "%H:%M"))
+            .map_err(|_| format!("Invalid time: {}", time))
+    };
+
+    let Some((start, end)) = value.split_once('-') else {
+        return Err("Expected a range of hours, as in 9-14".to_string());
+    };
+    let window = TimeWindow::new(parse_time(start)?, parse_time(end)?);
+    if window.start >= window.end {
+        return Err("The start of the range must be before its end".to_string());
+    }
+    Ok(window)
 }
 
 #[derive(Serialize)]
@@ -40,13 +108,21 @@ pub struct DayWithAppointments {
 #[derive(Serialize)]
 pub struct OfficeBasicInfo {
     office_id: u32,
-    office_name: &'static str,
+    office_name: String,
 }
 
 #[derive(Serialize)]
 pub struct OfficeAppoinmentsInfo {
     office: OfficeBasicInfo,
     appointments: Vec<DayWithAppointments>,
+
+    // Whether the office reported its available appointments as recently
+    // booked, instead of just not having any.
+    recently_booked: bool,
+
+    // Only present if the appointments of the office couldn't be fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -54,18 +130,19 @@ pub struct ProcudureAppointments {
     appointments_by_office: Vec<OfficeAppoinmentsInfo>,
 }
 
-pub fn basic_office_info_from_static(office: &StaticOffice) -> OfficeBasicInfo {
+pub fn basic_office_info(office: &DataGenOffice) -> OfficeBasicInfo {
     OfficeBasicInfo {
         office_id: office.id.0,
-        office_name: office.name,
+        office_name: office.name.clone(),
     }
 }
 
-fn filter_offices<T: Deref<Target = &'static StaticOffice>>(
-    mut offices: Vec<T>,
+fn filter_offices(
+    mut offices: Vec<&DataGenOffice>,
     match_office_id: Option<u32>,
     match_office_group: Option<String>,
-) -> Vec<T> {
+    match_location: Option<(Coordinates, f64)>,
+) -> Vec<&DataGenOffice> {
     if let Some(office_id) = match_office_id {
         offices.retain(|office| office.id.0 == office_id);
     }
@@ -75,26 +152,36 @@ fn filter_offices<T: Deref<Target = &'static StaticOffice>>(
         offices.retain(|office| office.group.to_lowercase() == search_group);
     }
 
+    if let Some((location, radius)) = match_location {
+        offices.retain(|office| {
+            office
+                .coordinates
+                .is_some_and(|coordinates| coordinates.distance_km(&location) <= radius)
+        });
+    }
+
     offices
 }
 
-pub async fn main(args: Args) -> anyhow::Result<ExitCode> {
+pub async fn main(args: Args, global: &GlobalArgs) -> anyhow::Result<ExitCode> {
     // Get all offices and apply filters
-    let all_offices: Vec<_> = madrid_cita_previa_data::offices::ALL.iter().collect();
-    let filtered_offices = filter_offices(all_offices, args.office_id, args.office_group);
+    let model = global.load_model()?;
+    let all_offices: Vec<_> = model.offices().iter().collect();
+    let filtered_offices = filter_offices(
+        all_offices,
+        args.office_id,
+        args.office_group,
+        args.near.zip(args.radius),
+    );
 
     let offices_with_procedure = filtered_offices
         .into_iter()
         .filter_map(|office| {
-            if let Some(proc) = office
+            office
                 .procedures
                 .iter()
                 .find(|proc| proc.procedure_id.0 == args.procedure_id)
-            {
-                Some((office, proc))
-            } else {
-                None
-            }
+                .map(|proc| (office, proc))
         })
         .collect::<Vec<_>>();
 
@@ -103,75 +190,112 @@ pub async fn main(args: Args) -> anyhow::Result<ExitCode> {
         return Ok(ExitCode::FaultOrArgsError);
     }
 
-    let session = AppointmentSession::new(ClientBuilder::new());
+    let session = global.build_session()?;
     let mut found_appointments = false;
+    let mut found_errors = false;
     let mut acc_appointments: Vec<OfficeAppoinmentsInfo> = Vec::new();
 
-    for (office, procedure) in offices_with_procedure {
-        let appointments = session
-            .get_appointments_for_office(office.id, procedure.procedure_office_id)
-            .await?;
-
-        let mut slots_by_day: Vec<(NaiveDate, Option<Vec<DateTime<chrono_tz::Tz>>>)> = Vec::new();
-        // Sometimes it may happen that get_appointments_for_office reports a
-        // day with appointments, but then no slots are avaiable. For such
-        // reason, setting the found_appointments differently depending on
-        // what data are being asked to get.
-        if args.slots {
-            for appointment_day in appointments.into_iter() {
-                let slots_for_current_day: Vec<DateTime<chrono_tz::Tz>> = session
-                    .get_available_appointment_slots_for_office_day(
-                        procedure.procedure_office_id,
-                        appointment_day,
-                    )
-                    .await?
-                    .collect();
-
-                if !slots_for_current_day.is_empty() {
-                    found_appointments = true;
-                    // Only register this day as a "day with appointments" if we
-                    // actually found any slot in the returned day.
-                    slots_by_day.push((appointment_day, Some(slots_for_current_day)));
-                }
-            }
+    let queries = offices_with_procedure
+        .iter()
+        .map(|(office, procedure)| OfficeQuery {
+            office_id: office.id,
+            procedure_office_id: procedure.procedure_office_id,
+        });
+    let mut date_range = match args.within_days {
+        Some(days) => DateRange::within_days(days),
+        None => DateRange::new(None, args.to),
+    };
+    if args.from.is_some() {
+        date_range.from = args.from;
+    }
 
-            if !args.json {
-                println!(
-                    "{}: {:?}",
-                    office.name,
-                    slots_by_day
-                        .iter()
-                        .flat_map(|(_, slot)| slot)
-                        .collect::<Vec<_>>()
-                );
-            }
-        } else {
-            if !appointments.is_empty() {
-                found_appointments = true;
-            }
+    let options = AvailabilityQueryOptions {
+        concurrency: args.concurrency,
+        fetch_slots: args.slots,
+        date_range,
+        time_window: args.between_hours,
+        ..Default::default()
+    };
+    // Only NDJSON output is printed as soon as the results of each office are
+    // available. Otherwise, results are printed in the same order as the
+    // offices in the model, so the output doesn't change from run to run.
+    let mut results = if args.ndjson {
+        session
+            .stream_offices_availability(ProcedureId(args.procedure_id), queries, &options)
+            .boxed_local()
+    } else {
+        stream::iter(
+            session
+                .query_offices_availability(ProcedureId(args.procedure_id), queries, &options)
+                .await,
+        )
+        .boxed_local()
+    };
 
-            slots_by_day.extend(appointments.iter().map(|day| (*day, None)));
-            if !args.json {
-                println!("{}: {:?}", office.name, appointments);
+    while let Some(result) = results.next().await {
+        let (office, _) = offices_with_procedure
+            .iter()
+            .find(|(office, _)| office.id == result.office_id)
+            .unwrap();
+
+        let (days, error) = match result.days {
+            Ok(days) => (days, None),
+            Err(err) => {
+                eprintln!("{}: Couldn't fetch appointments: {}", office.name, err);
+                found_errors = true;
+                (Vec::new(), Some(err.to_string()))
             }
+        };
+
+        // When slots are requested, days reported to have appointments but
+        // without any available slot are already left out by the library.
+        if !days.is_empty() {
+            found_appointments = true;
         }
 
-        if args.json {
-            acc_appointments.push(OfficeAppoinmentsInfo {
-                office: basic_office_info_from_static(office),
-                appointments: slots_by_day
+        if args.json || args.ndjson {
+            let info = OfficeAppoinmentsInfo {
+                office: basic_office_info(office),
+                appointments: days
                     .iter()
-                    .map(|(day, slots)| DayWithAppointments {
-                        day: day.to_string(),
-                        slots: match slots {
-                            None => None,
-                            Some(slots) => {
-                                Some(slots.iter().map(|slot| slot.timestamp()).collect())
-                            }
-                        },
+                    .map(|day| DayWithAppointments {
+                        day: day.day.to_string(),
+                        slots: day
+                            .slots
+                            .as_ref()
+                            .map(|slots| slots.iter().map(|slot| slot.time.timestamp()).collect()),
                     })
                     .collect(),
-            });
+                recently_booked: result.recently_booked,
+                error,
+            };
+
+            if args.ndjson {
+                println!("{}", serde_json::to_string(&info).unwrap());
+            } else {
+                acc_appointments.push(info);
+            }
+        } else if error.is_none() {
+            if result.recently_booked {
+                println!(
+                    "{}: Available appointments have been recently booked",
+                    office.name
+                );
+            } else if args.slots {
+                println!(
+                    "{}: {:?}",
+                    office.name,
+                    days.iter()
+                        .flat_map(|day| day.slots.iter().flatten().map(|slot| slot.time))
+                        .collect::<Vec<_>>()
+                );
+            } else {
+                println!(
+                    "{}: {:?}",
+                    office.name,
+                    days.iter().map(|day| day.day).collect::<Vec<_>>()
+                );
+            }
         }
     }
     if args.json {
@@ -185,10 +309,15 @@ pub async fn main(args: Args) -> anyhow::Result<ExitCode> {
     }
 
     if !found_appointments {
-        if !args.json {
+        if !args.json && !args.ndjson {
             eprintln!("No appointments found in any of the filtered offices.");
         }
-        Ok(ExitCode::RequestUnsatisfied)
+
+        if found_errors {
+            Ok(ExitCode::FaultOrArgsError)
+        } else {
+            Ok(ExitCode::RequestUnsatisfied)
+        }
     } else {
         Ok(ExitCode::Ok)
     }
@@ -1,8 +1,6 @@
-use std::ops::Deref;
+use madrid_cita_previa::DataGenOffice;
 
-use madrid_cita_previa::StaticOffice;
-
-use super::ExitCode;
+use super::{ExitCode, GlobalArgs};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -13,12 +11,17 @@ pub struct Args {
     /// Filter offices by those that can handle the given procedure
     #[arg(short, long)]
     pub procedure: Option<u32>,
+
+    /// Filter offices by district name
+    #[arg(short, long)]
+    pub district: Option<String>,
 }
 
-fn print_offices<T: Deref<Target = &'static StaticOffice>>(
-    mut offices: Vec<T>,
+fn print_offices(
+    mut offices: Vec<&DataGenOffice>,
     filter_by_group: Option<String>,
     filter_by_procedure: Option<u32>,
+    filter_by_district: Option<String>,
 ) {
     if let Some(group) = filter_by_group {
         let search_group = group.to_lowercase();
@@ -35,8 +38,14 @@ fn print_offices<T: Deref<Target = &'static StaticOffice>>(
         });
     }
 
-    println!("{:<5} | {:<40} | {}", "ID", "Group", "Name");
-    offices.sort_by(|a, b| Ord::cmp(a.group, b.group).then(Ord::cmp(a.name, b.name)));
+    if let Some(district) = filter_by_district {
+        let search_district = district.to_lowercase();
+
+        offices.retain(|office| office.district_name.to_lowercase() == search_district);
+    }
+
+    println!("{:<5} | {:<40} | Name", "ID", "Group");
+    offices.sort_by(|a, b| Ord::cmp(&a.group, &b.group).then(Ord::cmp(&a.name, &b.name)));
 
     for office in offices {
         println!(
@@ -46,11 +55,13 @@ fn print_offices<T: Deref<Target = &'static StaticOffice>>(
     }
 }
 
-pub async fn main(args: Args) -> anyhow::Result<ExitCode> {
+pub async fn main(args: Args, global: &GlobalArgs) -> anyhow::Result<ExitCode> {
+    let model = global.load_model()?;
     print_offices(
-        madrid_cita_previa_data::offices::ALL.iter().collect(),
+        model.offices().iter().collect(),
         args.group,
         args.procedure,
+        args.district,
     );
     Ok(ExitCode::Ok)
 }
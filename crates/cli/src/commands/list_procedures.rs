@@ -1,4 +1,4 @@
-use super::ExitCode;
+use super::{ExitCode, GlobalArgs};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -7,18 +7,20 @@ pub struct Args {
     pub category: Option<String>,
 }
 
-pub async fn main(args: Args) -> anyhow::Result<ExitCode> {
+pub async fn main(args: Args, global: &GlobalArgs) -> anyhow::Result<ExitCode> {
+    let model = global.load_model()?;
     let procedures = if let Some(filter) = args.category {
         let filter_category = filter.to_lowercase();
 
-        madrid_cita_previa_data::procedures::ALL
+        model
+            .procedures()
             .iter()
             .filter(|proc| proc.procedure_category.to_lowercase() == filter_category)
             .collect::<Vec<_>>()
     } else {
-        madrid_cita_previa_data::procedures::ALL.iter().collect()
+        model.procedures().iter().collect()
     };
-    println!("{:<5} | {:<40} | {}", "ID", "Category", "Name");
+    println!("{:<5} | {:<40} | Name", "ID", "Category");
 
     for procedure in procedures {
         println!(
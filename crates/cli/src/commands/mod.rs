@@ -1,10 +1,15 @@
-use std::process::Termination;
+use std::{path::PathBuf, process::Termination};
+
+use anyhow::Context;
+use log::warn;
+use madrid_cita_previa::{AppointmentSession, DataModel, RateLimit};
 
 pub mod fetch_closest_appointment_office;
 pub mod fetch_procedure_appointments;
 pub mod list_offices;
 pub mod list_procedures;
 pub mod office_info;
+pub mod refresh_data;
 
 #[repr(u8)]
 pub enum ExitCode {
@@ -18,3 +23,65 @@ impl Termination for ExitCode {
         std::process::ExitCode::from(self as u8)
     }
 }
+
+#[derive(clap::Args)]
+pub struct GlobalArgs {
+    /// Maximum number of requests per second sent to the server
+    #[arg(long, global = true)]
+    pub rate_limit: Option<f64>,
+
+    /// Maximum number of requests sent at once when --rate-limit is set
+    #[arg(long, global = true, default_value_t = 1)]
+    pub rate_limit_burst: u32,
+
+    /// Keep the session in this file, so later runs reuse it instead of
+    /// starting a new one
+    #[arg(long, global = true)]
+    pub session_file: Option<PathBuf>,
+
+    /// Read the offices and procedures from this model file, as generated by
+    /// the datagen binary or refresh-data, instead of the one kept by
+    /// refresh-data or the one built into the CLI
+    #[arg(long, global = true)]
+    pub model: Option<PathBuf>,
+}
+
+impl GlobalArgs {
+    pub fn build_session(&self) -> anyhow::Result<AppointmentSession> {
+        let mut builder = AppointmentSession::builder();
+        if let Some(rate_limit) = self.rate_limit {
+            builder = builder.rate_limit(RateLimit::new(rate_limit, self.rate_limit_burst));
+        }
+        if let Some(session_file) = &self.session_file {
+            builder = builder.state_file(session_file);
+        }
+        Ok(builder.build()?)
+    }
+
+    pub fn load_model(&self) -> anyhow::Result<DataModel> {
+        if let Some(path) = &self.model {
+            return DataModel::from_file(path)
+                .with_context(|| format!("Couldn't load model from {}", path.display()));
+        }
+
+        // A model downloaded by refresh-data is fresher than the built-in one,
+        // but it isn't worth failing over if it can't be read.
+        if let Some(path) = DataModel::user_data_path()
+            && path.exists()
+        {
+            match DataModel::from_file(&path) {
+                Ok(model) => return Ok(model),
+                Err(err) => warn!(
+                    "Couldn't load model from {}, using the built-in one: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+
+        Ok(DataModel::from_static(
+            madrid_cita_previa_data::offices::ALL,
+            madrid_cita_previa_data::procedures::ALL,
+        ))
+    }
+}
@@ -1,33 +1,118 @@
-use super::ExitCode;
+use madrid_cita_previa::{OfficeExtendedInfo, OfficeId};
+
+use super::{ExitCode, GlobalArgs};
 
 #[derive(clap::Args)]
 pub struct Args {
     /// Office ID to get information about
     #[arg(short, long)]
     pub office_id: u32,
+
+    /// Download also the opening hours, phone, accessibility and transport
+    /// information of the office from its information page
+    #[arg(short, long)]
+    pub extended: bool,
+}
+
+fn print_extended_info(info: &OfficeExtendedInfo) {
+    println!(" - Information page: {}", info.url);
+
+    if info.opening_hours.is_empty() {
+        if let Some(text) = &info.opening_hours_text {
+            println!(" - Opening hours: {}", text);
+        }
+    } else {
+        println!(" - Opening hours:");
+        for day in &info.opening_hours {
+            let hours = day
+                .hours
+                .iter()
+                .map(|window| {
+                    format!(
+                        "{}-{}",
+                        window.start.format("%H:%M"),
+                        window.end.format("%H:%M")
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("  - {}: {}", day.weekday, hours);
+        }
+    }
+
+    if let Some(phone) = &info.phone {
+        println!(" - Phone: {}", phone);
+    }
+
+    if !info.accessibility.is_empty() {
+        println!(" - Accessibility:");
+        for note in &info.accessibility {
+            println!("  - {}", note);
+        }
+    }
+
+    if !info.transport.is_empty() {
+        println!(" - Transport:");
+        for line in &info.transport {
+            println!("  - {}", line);
+        }
+    }
 }
 
-pub async fn main(args: Args) -> anyhow::Result<ExitCode> {
-    let office = madrid_cita_previa_data::offices::ALL
-        .iter()
-        .find(|office| office.id.0 == args.office_id)
+pub async fn main(args: Args, global: &GlobalArgs) -> anyhow::Result<ExitCode> {
+    let model = global.load_model()?;
+    let office = model
+        .office(OfficeId(args.office_id))
         .ok_or_else(|| anyhow::anyhow!("Office with ID {} not found", args.office_id))?;
 
     println!("Office Information:");
     println!(" - ID: {}", office.id.0);
     println!(" - Name: {}", office.name);
     println!(" - Group: {}", office.group);
-    println!(" - Available Procedures:");
+    if !office.address.is_empty() {
+        println!(" - Address: {}", office.address);
+    }
+    if !office.district_name.is_empty() {
+        println!(" - District: {}", office.district_name);
+    }
+    if let Some(coordinates) = office.coordinates {
+        println!(
+            " - Coordinates: {}, {}",
+            coordinates.latitude, coordinates.longitude
+        );
+    }
 
-    for procedure in office.procedures {
-        let proc_info = madrid_cita_previa_data::procedures::ALL
-            .iter()
-            .find(|p| p.procedure_id == procedure.procedure_id)
-            .unwrap();
+    if args.extended {
+        // Models generated with the extended information already have it, so
+        // it is only downloaded otherwise.
+        let info = match &office.extended_info {
+            Some(info) => Some(info.clone()),
+            None => {
+                let session = global.build_session()?;
+                if !office.url.trim().is_empty() {
+                    Some(session.get_office_extended_info(&office.url).await?)
+                } else {
+                    // Models generated before the URL of the information page
+                    // was kept don't have it, so it is looked up in the office
+                    // details.
+                    session
+                        .get_office_extended_info_by_id(OfficeId(args.office_id))
+                        .await?
+                }
+            }
+        };
+        match info {
+            Some(info) => print_extended_info(&info),
+            None => eprintln!("The office has no information page."),
+        }
+    }
+
+    println!(" - Available Procedures:");
 
+    for procedure in &office.procedures {
         println!(
             "  - {} (ID: {}; Procedure Office ID: {})",
-            proc_info.procedure_name, procedure.procedure_id.0, procedure.procedure_office_id.0
+            procedure.procedure_name, procedure.procedure_id.0, procedure.procedure_office_id.0
         );
     }
 
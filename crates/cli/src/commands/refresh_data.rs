@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use madrid_cita_previa::{DataModel, DataModelDownloadOptions};
+
+use super::{ExitCode, GlobalArgs};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Download only the offices in the given group. Requires --output, since
+    /// the model in the user data directory replaces the built-in one
+    #[arg(long, requires = "output")]
+    filter_group: Option<String>,
+
+    /// Download only the offices whose name contains the given string.
+    /// Requires --output, since the model in the user data directory replaces
+    /// the built-in one
+    #[arg(long, requires = "output")]
+    filter_name: Option<String>,
+
+    /// Download also the opening hours, phone, accessibility and transport
+    /// information of each office from its information page
+    #[arg(long)]
+    extended_info: bool,
+
+    /// Write the model to this file instead of the user data directory
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub async fn main(args: Args, global: &GlobalArgs) -> anyhow::Result<ExitCode> {
+    let Some(output) = args.output.or_else(DataModel::user_data_path) else {
+        eprintln!("Couldn't find the user data directory, use --output instead.");
+        return Ok(ExitCode::FaultOrArgsError);
+    };
+
+    let session = global.build_session()?;
+    let model = session
+        .download_data_model(&DataModelDownloadOptions {
+            filter_group: args.filter_group,
+            filter_name: args.filter_name,
+            extended_info: args.extended_info,
+        })
+        .await?;
+
+    model
+        .save(&output)
+        .with_context(|| format!("Couldn't save model to {}", output.display()))?;
+    eprintln!(
+        "Saved {} offices and {} procedures to {}",
+        model.offices().len(),
+        model.procedures().len(),
+        output.display()
+    );
+
+    Ok(ExitCode::Ok)
+}
@@ -1,4 +1,3 @@
-
 use clap::{Parser, Subcommand};
 use log::LevelFilter;
 mod commands;
@@ -10,6 +9,9 @@ mod commands;
 struct Cli {
     #[command(subcommand)]
     subcommand: Commands,
+
+    #[command(flatten)]
+    global: commands::GlobalArgs,
 }
 
 #[derive(Subcommand)]
@@ -24,6 +26,9 @@ enum Commands {
     FetchClosestAppointmentOffice(commands::fetch_closest_appointment_office::Args),
     /// Find the appointments for a given procedure
     FetchProcedureAppointments(commands::fetch_procedure_appointments::Args),
+    /// Download the offices and procedures, so later commands use them
+    /// instead of the built-in ones
+    RefreshData(commands::refresh_data::Args),
 }
 
 #[tokio::main]
@@ -36,14 +41,17 @@ async fn main() -> anyhow::Result<commands::ExitCode> {
     let cli = Cli::parse();
 
     Ok(match cli.subcommand {
-        Commands::ListOffices(args) => commands::list_offices::main(args).await?,
-        Commands::ListProcedures(args) => commands::list_procedures::main(args).await?,
-        Commands::OfficeInfo(args) => commands::office_info::main(args).await?,
+        Commands::ListOffices(args) => commands::list_offices::main(args, &cli.global).await?,
+        Commands::ListProcedures(args) => {
+            commands::list_procedures::main(args, &cli.global).await?
+        }
+        Commands::OfficeInfo(args) => commands::office_info::main(args, &cli.global).await?,
         Commands::FetchClosestAppointmentOffice(args) => {
-            commands::fetch_closest_appointment_office::main(args).await?
+            commands::fetch_closest_appointment_office::main(args, &cli.global).await?
         }
         Commands::FetchProcedureAppointments(args) => {
-            commands::fetch_procedure_appointments::main(args).await?
+            commands::fetch_procedure_appointments::main(args, &cli.global).await?
         }
+        Commands::RefreshData(args) => commands::refresh_data::main(args, &cli.global).await?,
     })
 }
@@ -7,8 +7,8 @@ use std::{
 
 use lazy_static::lazy_static;
 use madrid_cita_previa::{
-    DataGenModel, DataGenOffice, DataGenOfficeProcedure, DataGenProcedure, OfficeId, ProcedureId,
-    ProcedureOfficeId,
+    Coordinates, DataGenModel, DataGenOffice, DataGenOfficeProcedure, DataGenProcedure, OfficeId,
+    ProcedureId, ProcedureOfficeId,
 };
 use proc_macro2::{Ident, Literal, Span, TokenStream};
 use quote::{TokenStreamExt, quote};
@@ -39,6 +39,31 @@ fn gen_proc_id(proc_id: ProcedureId) -> TokenStream {
     }
 }
 
+fn gen_coordinates(coordinates: Option<Coordinates>) -> TokenStream {
+    match coordinates {
+        Some(coordinates)
+            if coordinates.latitude.is_finite() && coordinates.longitude.is_finite() =>
+        {
+            let latitude = Literal::f64_unsuffixed(coordinates.latitude);
+            let longitude = Literal::f64_unsuffixed(coordinates.longitude);
+            quote! {
+                Some(::madrid_cita_previa::Coordinates::new(#latitude, #longitude))
+            }
+        }
+        _ => quote! { None },
+    }
+}
+
+fn gen_optional_str(value: Option<&str>) -> TokenStream {
+    match value {
+        Some(value) => {
+            let lit = Literal::string(value);
+            quote! { Some(#lit) }
+        }
+        None => quote! { None },
+    }
+}
+
 fn clean_ident_name(source: &str) -> String {
     let mut clean: String = RE_DENIED_IDENT_CHARS.replace_all(source, "_").to_string();
     while clean.contains("__") {
@@ -88,10 +113,13 @@ fn gen_office(office: &DataGenOffice) -> TokenStream {
     let office_name_lit = Literal::string(&office.name);
     let office_group_lit = Literal::string(&office.group);
     let office_id = gen_office_id(office.id);
-    let procedures = office
-        .procedures
-        .iter()
-        .map(|proc| gen_office_procedure(proc));
+    let procedures = office.procedures.iter().map(gen_office_procedure);
+    let coordinates = gen_coordinates(office.coordinates);
+    let address_lit = Literal::string(&office.address);
+    let district_code_lit = Literal::string(&office.district_code);
+    let district_name_lit = Literal::string(&office.district_name);
+    let office_code = gen_optional_str(office.office_code.as_deref());
+    let url_lit = Literal::string(&office.url);
 
     quote! {
         pub const #office_const: ::madrid_cita_previa::StaticOffice = ::madrid_cita_previa::StaticOffice {
@@ -100,7 +128,13 @@ fn gen_office(office: &DataGenOffice) -> TokenStream {
             id: #office_id,
             procedures: &[
                 #(#procedures),*
-            ]
+            ],
+            coordinates: #coordinates,
+            address: #address_lit,
+            district_code: #district_code_lit,
+            district_name: #district_name_lit,
+            office_code: #office_code,
+            url: #url_lit,
         };
     }
 }
@@ -113,7 +147,7 @@ fn gen_offices_mod(model: &DataGenModel) -> TokenStream {
         }
     });
 
-    let all_gen_offices = model.offices.iter().map(|office| gen_office(office));
+    let all_gen_offices = model.offices.iter().map(gen_office);
 
     quote! {
         pub mod offices {
@@ -149,7 +183,7 @@ fn gen_procedures_mod(model: &DataGenModel) -> TokenStream {
         }
     });
 
-    let all_procs = model.procedures.iter().map(|proc| gen_procedure(proc));
+    let all_procs = model.procedures.iter().map(gen_procedure);
 
     quote! {
         pub mod procedures {
@@ -167,10 +201,12 @@ fn main() {
     println!("cargo::rerun-if-changed={}", MODEL_PATH);
     let mut model_contents: String = String::new();
     File::open(MODEL_PATH)
-        .expect(&format!(
-            "Couldn't open model file at {}. Have you ran the datagen script?",
-            MODEL_PATH
-        ))
+        .unwrap_or_else(|_| {
+            panic!(
+                "Couldn't open model file at {}. Have you ran the datagen script?",
+                MODEL_PATH
+            )
+        })
         .read_to_string(&mut model_contents)
         .unwrap();
     let datagen_model: DataGenModel = serde_json::from_str(&model_contents).unwrap();
@@ -186,6 +222,6 @@ fn main() {
         .join("gen.rs");
     File::create(out_path)
         .unwrap()
-        .write_all(&str.as_bytes())
+        .write_all(str.as_bytes())
         .unwrap();
 }
@@ -0,0 +1,785 @@
+use chrono::{Days, NaiveDate, NaiveTime, Utc};
+use futures_util::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AppointmentSession, AttentionType, DaySlotsQuery, Error, NetOfficeModel, OfficeId, ProcedureId,
+    ProcedureOfficeId, Result, Slot,
+};
+
+/// An office to query the availability of a procedure in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfficeQuery {
+    pub office_id: OfficeId,
+    pub procedure_office_id: ProcedureOfficeId,
+}
+
+/// Options for querying the availability of a procedure in several offices.
+#[derive(Debug, Clone)]
+pub struct AvailabilityQueryOptions {
+    /// Maximum number of offices queried at the same time.
+    pub concurrency: usize,
+    /// Whether to fetch the available slots of each day with appointments.
+    pub fetch_slots: bool,
+    /// The kind of attention the slots are fetched for.
+    pub attention_type: AttentionType,
+    /// Only days within this range are reported, and the slots of the rest of
+    /// them are not fetched.
+    pub date_range: DateRange,
+    /// Only slots starting within this time of the day are reported. Days
+    /// without available slots within it are left out. Only applies when
+    /// `fetch_slots` is set.
+    pub time_window: Option<TimeWindow>,
+}
+
+impl Default for AvailabilityQueryOptions {
+    fn default() -> Self {
+        AvailabilityQueryOptions {
+            concurrency: 4,
+            fetch_slots: false,
+            attention_type: AttentionType::default(),
+            date_range: DateRange::default(),
+            time_window: None,
+        }
+    }
+}
+
+/// A range of days, both ends included. A missing end leaves the range open
+/// on that side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn new(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Self {
+        DateRange { from, to }
+    }
+
+    /// The range from today to `days` days after today, in Madrid time.
+    pub fn within_days(days: u64) -> Self {
+        let today = Utc::now()
+            .with_timezone(&chrono_tz::Europe::Madrid)
+            .date_naive();
+        DateRange {
+            from: Some(today),
+            to: today.checked_add_days(Days::new(days)),
+        }
+    }
+
+    pub fn contains(&self, day: NaiveDate) -> bool {
+        self.from.is_none_or(|from| day >= from) && self.to.is_none_or(|to| day <= to)
+    }
+}
+
+/// A time of the day window, in Madrid time. The start is included and the
+/// end is excluded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        TimeWindow { start, end }
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        time >= self.start && time < self.end
+    }
+}
+
+/// The days with available appointments in an office, as reported by the
+/// office appointments page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", content = "days", rename_all = "snake_case")]
+pub enum AppointmentDays {
+    /// The days with available appointments, which may be none.
+    Available(Vec<NaiveDate>),
+    /// The page reported that the available appointments in the office have
+    /// been booked recently, which usually means slots were just released and
+    /// taken.
+    RecentlyBooked,
+}
+
+impl AppointmentDays {
+    pub fn days(&self) -> &[NaiveDate] {
+        match self {
+            AppointmentDays::Available(days) => days,
+            AppointmentDays::RecentlyBooked => &[],
+        }
+    }
+
+    pub fn into_days(self) -> Vec<NaiveDate> {
+        match self {
+            AppointmentDays::Available(days) => days,
+            AppointmentDays::RecentlyBooked => Vec::new(),
+        }
+    }
+
+    pub fn is_recently_booked(&self) -> bool {
+        matches!(self, AppointmentDays::RecentlyBooked)
+    }
+
+    /// Keeps only the days within the given range.
+    pub fn within(self, range: &DateRange) -> Self {
+        match self {
+            AppointmentDays::Available(mut days) => {
+                days.retain(|day| range.contains(*day));
+                AppointmentDays::Available(days)
+            }
+            AppointmentDays::RecentlyBooked => AppointmentDays::RecentlyBooked,
+        }
+    }
+}
+
+/// The days with available appointments for a procedure, queried through the
+/// procedure route of the website.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProcedureAppointments {
+    /// The office the appointments belong to, if known.
+    pub office_id: Option<OfficeId>,
+    /// The id of the procedure in the office, if the server reported it.
+    pub procedure_office_id: Option<ProcedureOfficeId>,
+    pub days: AppointmentDays,
+}
+
+/// A day with available appointments in an office.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DayAvailability {
+    pub day: NaiveDate,
+    /// The available slots of the day, if they were requested.
+    pub slots: Option<Vec<Slot>>,
+}
+
+/// The days with available appointments for a procedure in an office.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OfficeAvailability {
+    pub procedure_id: ProcedureId,
+    pub office_id: OfficeId,
+    pub procedure_office_id: ProcedureOfficeId,
+    pub days: Vec<DayAvailability>,
+    /// Whether the office reported its appointments as recently booked.
+    pub recently_booked: bool,
+}
+
+/// The outcome of querying the availability of a procedure in an office.
+#[derive(Debug)]
+pub struct OfficeAvailabilityResult {
+    pub procedure_id: ProcedureId,
+    pub office_id: OfficeId,
+    pub procedure_office_id: ProcedureOfficeId,
+    pub days: Result<Vec<DayAvailability>>,
+    /// Whether the office reported its appointments as recently booked.
+    /// Always false if the query failed.
+    pub recently_booked: bool,
+}
+
+impl OfficeAvailabilityResult {
+    pub fn into_availability(self) -> Result<OfficeAvailability> {
+        Ok(OfficeAvailability {
+            procedure_id: self.procedure_id,
+            office_id: self.office_id,
+            procedure_office_id: self.procedure_office_id,
+            days: self.days?,
+            recently_booked: self.recently_booked,
+        })
+    }
+}
+
+/// The office with the closest appointment for a procedure, along with its
+/// earliest day with available slots.
+#[derive(Debug)]
+pub struct ClosestAppointment {
+    pub office: NetOfficeModel,
+    /// The id of the procedure in the office.
+    pub procedure_office_id: ProcedureOfficeId,
+    /// The earliest day with available slots in the office, if any. May be
+    /// missing even if the server reported the office, since its appointments
+    /// may have been booked in the meantime.
+    pub date: Option<NaiveDate>,
+    /// The available slots of `date`, sorted by time.
+    pub slots: Vec<Slot>,
+}
+
+impl ClosestAppointment {
+    /// Returns the first available slot of the earliest day.
+    pub fn first_slot(&self) -> Option<&Slot> {
+        self.slots.first()
+    }
+}
+
+impl AppointmentSession {
+    /// Returns the office with the closest appointment for a procedure, along
+    /// with its earliest day with available slots, or `None` if no office has
+    /// appointments for it.
+    pub async fn get_closest_appointment(
+        &self,
+        procedure_id: ProcedureId,
+        attention_type: AttentionType,
+    ) -> Result<Option<ClosestAppointment>> {
+        let Some(office) = self.get_office_closest_appointment(procedure_id).await? else {
+            return Ok(None);
+        };
+
+        let Some(procedure_office_id) = office
+            .procedures
+            .iter()
+            .find(|proc| proc.procedure_id == procedure_id)
+            .map(|proc| proc.office_procedure_id)
+        else {
+            return Err(Error::unexpected_page(
+                &self.endpoints().closest_appointment_office,
+                format!(
+                    "Procedure {} not found in office {}",
+                    procedure_id.0, office.office_id
+                ),
+                "",
+            ));
+        };
+
+        let mut days = self
+            .get_appointments_for_office(OfficeId(office.office_id), procedure_office_id)
+            .await?
+            .into_days();
+        days.sort();
+
+        let last_day = days.last().copied();
+        for day in days {
+            let query = DaySlotsQuery {
+                attention_type,
+                last_day: Some(day) == last_day,
+            };
+            let mut slots: Vec<_> = self
+                .query_appointment_slots_for_office_day(procedure_office_id, day, &query)
+                .await?
+                .slots()
+                .filter(|slot| slot.available)
+                .copied()
+                .collect();
+
+            if !slots.is_empty() {
+                slots.sort_by_key(|slot| slot.time);
+                return Ok(Some(ClosestAppointment {
+                    office,
+                    procedure_office_id,
+                    date: Some(day),
+                    slots,
+                }));
+            }
+        }
+
+        Ok(Some(ClosestAppointment {
+            office,
+            procedure_office_id,
+            date: None,
+            slots: Vec::new(),
+        }))
+    }
+
+    /// Queries the days with available appointments for a procedure in each of
+    /// the given offices, querying up to `options.concurrency` offices at once.
+    ///
+    /// A failure when querying an office is reported in its result, without
+    /// affecting the rest of them. Results are returned in the same order as
+    /// the given offices.
+    pub async fn query_offices_availability(
+        &self,
+        procedure_id: ProcedureId,
+        offices: impl IntoIterator<Item = OfficeQuery>,
+        options: &AvailabilityQueryOptions,
+    ) -> Vec<OfficeAvailabilityResult> {
+        self.office_availability_queries(procedure_id, offices, options)
+            .buffered(options.concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Same as [`AppointmentSession::query_offices_availability`], but yields
+    /// the result of each office as soon as it is available, so results come
+    /// in completion order instead of the order of the given offices.
+    pub fn stream_offices_availability(
+        &self,
+        procedure_id: ProcedureId,
+        offices: impl IntoIterator<Item = OfficeQuery>,
+        options: &AvailabilityQueryOptions,
+    ) -> impl Stream<Item = OfficeAvailabilityResult> {
+        self.office_availability_queries(procedure_id, offices, options)
+            .buffer_unordered(options.concurrency.max(1))
+    }
+
+    /// Queries the days with available appointments for a procedure in each of
+    /// the given offices through the procedure route of the website, querying
+    /// up to `concurrency` offices at once.
+    ///
+    /// A failure when querying an office is reported in its result, without
+    /// affecting the rest of them. Results are returned in the same order as
+    /// the given offices.
+    pub async fn query_procedure_appointments(
+        &self,
+        procedure_id: ProcedureId,
+        offices: impl IntoIterator<Item = OfficeId>,
+        concurrency: usize,
+    ) -> Vec<(OfficeId, Result<ProcedureAppointments>)> {
+        stream::iter(offices)
+            .map(|office| async move {
+                let appointments = self
+                    .get_appointments_for_procedure(procedure_id, Some(office))
+                    .await;
+                (office, appointments)
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    fn office_availability_queries(
+        &self,
+        procedure_id: ProcedureId,
+        offices: impl IntoIterator<Item = OfficeQuery>,
+        options: &AvailabilityQueryOptions,
+    ) -> impl Stream<Item = impl Future<Output = OfficeAvailabilityResult>> {
+        let options = options.clone();
+        stream::iter(offices).map(move |office| {
+            let options = options.clone();
+            async move {
+                let (days, recently_booked) =
+                    match self.query_office_availability(office, &options).await {
+                        Ok((days, recently_booked)) => (Ok(days), recently_booked),
+                        Err(err) => (Err(err), false),
+                    };
+
+                OfficeAvailabilityResult {
+                    procedure_id,
+                    office_id: office.office_id,
+                    procedure_office_id: office.procedure_office_id,
+                    days,
+                    recently_booked,
+                }
+            }
+        })
+    }
+
+    async fn query_office_availability(
+        &self,
+        office: OfficeQuery,
+        options: &AvailabilityQueryOptions,
+    ) -> Result<(Vec<DayAvailability>, bool)> {
+        let appointment_days = self
+            .get_appointments_for_office(office.office_id, office.procedure_office_id)
+            .await?;
+        let recently_booked = appointment_days.is_recently_booked();
+        let days = appointment_days.into_days();
+
+        // The last day is the last one reported by the server, even if it is
+        // out of the range, since it's the one the server pages the slots of.
+        let last_day = days.iter().max().copied();
+        let days: Vec<_> = days
+            .into_iter()
+            .filter(|day| options.date_range.contains(*day))
+            .collect();
+
+        if !options.fetch_slots {
+            let availability = days
+                .into_iter()
+                .map(|day| DayAvailability { day, slots: None })
+                .collect();
+            return Ok((availability, recently_booked));
+        }
+
+        let mut availability = Vec::new();
+        for day in days {
+            let query = DaySlotsQuery {
+                attention_type: options.attention_type,
+                last_day: Some(day) == last_day,
+            };
+            let slots: Vec<_> = self
+                .query_appointment_slots_for_office_day(office.procedure_office_id, day, &query)
+                .await?
+                .slots()
+                .filter(|slot| slot.available)
+                .filter(|slot| {
+                    options
+                        .time_window
+                        .is_none_or(|window| window.contains(slot.time.time()))
+                })
+                .copied()
+                .collect();
+
+            // The server sometimes reports a day with appointments that turns
+            // out to have no available slots. Such days are not reported.
+            if !slots.is_empty() {
+                availability.push(DayAvailability {
+                    day,
+                    slots: Some(slots),
+                });
+            }
+        }
+        Ok((availability, recently_booked))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::{FakeTransport, param, response, slots_page};
+
+    const PROCEDURE: ProcedureId = ProcedureId(321);
+    const APPOINTMENTS_PAGE: &str = include_str!("../tests/fixtures/office_appointments.html");
+
+    fn office(id: u32) -> OfficeQuery {
+        OfficeQuery {
+            office_id: OfficeId(id),
+            procedure_office_id: ProcedureOfficeId(id * 10),
+        }
+    }
+
+    /// Serves the days of the appointments fixture for every office but the
+    /// third one, whose page lacks them. The first office answers last.
+    fn offices_transport() -> FakeTransport {
+        FakeTransport::new(|request| {
+            let path = request.url.path();
+            let body = if path.ends_with("/horarioOficina.do") {
+                match param(request, "idOficina").as_deref() {
+                    Some("3") => "<html><body></body></html>".to_string(),
+                    _ => APPOINTMENTS_PAGE.to_string(),
+                }
+            } else if path.ends_with("/franjasDia.do") {
+                slots_page(["09:00", "12:00"].map(Some))
+            } else {
+                String::new()
+            };
+            response(&request.url, body)
+        })
+        .with_delay(|request| match param(request, "idOficina").as_deref() {
+            Some("1") => Duration::from_millis(50),
+            _ => Duration::ZERO,
+        })
+    }
+
+    fn office_ids(results: &[OfficeAvailabilityResult]) -> Vec<u32> {
+        results.iter().map(|result| result.office_id.0).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn query_keeps_the_order_of_the_offices() {
+        let session = offices_transport().session();
+        let offices = [office(1), office(2), office(4)];
+
+        let results = session
+            .query_offices_availability(PROCEDURE, offices, &AvailabilityQueryOptions::default())
+            .await;
+        assert_eq!(office_ids(&results), [1, 2, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_yields_results_as_they_complete() {
+        let session = offices_transport().session();
+        let offices = [office(1), office(2), office(4)];
+
+        let results: Vec<_> = session
+            .stream_offices_availability(PROCEDURE, offices, &AvailabilityQueryOptions::default())
+            .collect()
+            .await;
+        assert_eq!(office_ids(&results), [2, 4, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn procedure_route_queries_each_office() {
+        let session = offices_transport().session();
+        let offices = [OfficeId(1), OfficeId(3), OfficeId(4)];
+
+        let results = session
+            .query_procedure_appointments(PROCEDURE, offices, 2)
+            .await;
+        let office_ids: Vec<_> = results.iter().map(|(office, _)| office.0).collect();
+        assert_eq!(office_ids, [1, 3, 4]);
+        assert!(results[1].1.is_err());
+        for (office, appointments) in [&results[0], &results[2]] {
+            let appointments = appointments.as_ref().unwrap();
+            assert_eq!(appointments.office_id, Some(*office));
+            assert_eq!(appointments.days.days().len(), 3);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_office_only_affects_its_result() {
+        let session = offices_transport().session();
+        let offices = [office(1), office(3), office(4)];
+
+        let results = session
+            .query_offices_availability(PROCEDURE, offices, &AvailabilityQueryOptions::default())
+            .await;
+        assert_eq!(office_ids(&results), [1, 3, 4]);
+        assert!(matches!(results[1].days, Err(Error::UnexpectedPage { .. })));
+        for result in [&results[0], &results[2]] {
+            let days = result.days.as_ref().unwrap();
+            assert_eq!(days.len(), 3);
+            assert!(days.iter().all(|day| day.slots.is_none()));
+        }
+    }
+
+    /// Serves the days of the appointments fixture, with slots at different
+    /// times for each of them.
+    fn slots_transport() -> FakeTransport {
+        FakeTransport::new(|request| {
+            let path = request.url.path();
+            let body = if path.ends_with("/horarioOficina.do") {
+                APPOINTMENTS_PAGE.to_string()
+            } else if path.ends_with("/franjasDia.do") && param(request, "nh").unwrap() == "0" {
+                match param(request, "dia").unwrap().as_str() {
+                    "03/11/2026" => slots_page(["09:00", "12:00"].map(Some)),
+                    "04/11/2026" => slots_page(["17:00"].map(Some)),
+                    _ => slots_page(["12:30"].map(Some)),
+                }
+            } else if path.ends_with("/franjasDia.do") {
+                "[]".to_string()
+            } else {
+                String::new()
+            };
+            response(&request.url, body)
+        })
+    }
+
+    /// Returns the day and whether it was queried as the last one, for each
+    /// slots request received by the transport.
+    fn slots_requests(transport: &FakeTransport) -> Vec<(String, String)> {
+        transport
+            .requests()
+            .iter()
+            .filter(|request| request.url.path().ends_with("/franjasDia.do"))
+            .filter(|request| param(request, "nh").unwrap() == "0")
+            .map(|request| {
+                (
+                    param(request, "dia").unwrap(),
+                    param(request, "esUltimoDiaHuecos").unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 11, day).unwrap()
+    }
+
+    async fn query_days(
+        transport: &FakeTransport,
+        options: &AvailabilityQueryOptions,
+    ) -> Vec<(NaiveDate, Vec<NaiveTime>)> {
+        let mut results = transport
+            .session()
+            .query_offices_availability(PROCEDURE, [office(1)], options)
+            .await;
+        results
+            .pop()
+            .unwrap()
+            .days
+            .unwrap()
+            .into_iter()
+            .map(|day| {
+                let times = day.slots.unwrap().into_iter().map(|slot| slot.time.time());
+                (day.day, times.collect())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn last_day_is_queried_as_such() {
+        let transport = slots_transport();
+        let options = AvailabilityQueryOptions {
+            fetch_slots: true,
+            ..Default::default()
+        };
+
+        let days = query_days(&transport, &options).await;
+        assert_eq!(
+            days,
+            [
+                (date(3), vec![time(9, 0), time(12, 0)]),
+                (date(4), vec![time(17, 0)]),
+                (date(12), vec![time(12, 30)]),
+            ]
+        );
+        assert_eq!(
+            slots_requests(&transport),
+            [
+                ("03/11/2026".to_string(), "false".to_string()),
+                ("04/11/2026".to_string(), "false".to_string()),
+                ("12/11/2026".to_string(), "true".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn days_out_of_range_are_not_queried() {
+        let transport = slots_transport();
+        let options = AvailabilityQueryOptions {
+            fetch_slots: true,
+            date_range: DateRange::new(None, Some(date(4))),
+            ..Default::default()
+        };
+
+        let days = query_days(&transport, &options).await;
+        assert_eq!(
+            days.iter().map(|(day, _)| *day).collect::<Vec<_>>(),
+            [date(3), date(4)]
+        );
+        // The last day reported by the server is still the one out of the
+        // range, so none of the queried days is the last one.
+        assert_eq!(
+            slots_requests(&transport),
+            [
+                ("03/11/2026".to_string(), "false".to_string()),
+                ("04/11/2026".to_string(), "false".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn slots_out_of_time_window_are_left_out() {
+        let transport = slots_transport();
+        let options = AvailabilityQueryOptions {
+            fetch_slots: true,
+            time_window: Some(TimeWindow::new(time(10, 0), time(14, 0))),
+            ..Default::default()
+        };
+
+        let days = query_days(&transport, &options).await;
+        assert_eq!(
+            days,
+            [(date(3), vec![time(12, 0)]), (date(12), vec![time(12, 30)]),]
+        );
+    }
+
+    /// Body of the office with the closest appointment, offering the procedure
+    /// with the given id as the one with id 3210 in the office.
+    fn closest_office(procedure_id: u32) -> String {
+        format!(
+            r#"{{
+                "idOficina": 1,
+                "codIntegracion": null,
+                "latitud": 40.4168,
+                "longitud": -3.7038,
+                "nombreOficina": "OAC Centro",
+                "direccion": "Calle Mayor 1",
+                "codigoDistrito": "01",
+                "nombreDistrito": "Centro",
+                "urlInformacion": "",
+                "tramites": [{{
+                    "categoria": "Padrón",
+                    "nombreTramite": "Empadronamiento",
+                    "idTramite": 3210,
+                    "idFamiliaCita": {}
+                }}]
+            }}"#,
+            procedure_id
+        )
+    }
+
+    /// Serves the office with the closest appointment, offering the given
+    /// procedure, and the days of the appointments fixture, with the slots
+    /// returned by the closure for each of them.
+    fn closest_transport(
+        procedure_id: u32,
+        day_slots: impl Fn(&str) -> Vec<&'static str> + Send + Sync + 'static,
+    ) -> FakeTransport {
+        FakeTransport::new(move |request| {
+            let path = request.url.path();
+            let body = if path.ends_with("/oficinaCitaProxima.do") {
+                closest_office(procedure_id)
+            } else if path.ends_with("/horarioOficina.do") {
+                APPOINTMENTS_PAGE.to_string()
+            } else if path.ends_with("/franjasDia.do") && param(request, "nh").unwrap() == "0" {
+                slots_page(
+                    day_slots(&param(request, "dia").unwrap())
+                        .into_iter()
+                        .map(Some),
+                )
+            } else if path.ends_with("/franjasDia.do") {
+                "[]".to_string()
+            } else {
+                String::new()
+            };
+            response(&request.url, body)
+        })
+    }
+
+    async fn closest_appointment(transport: &FakeTransport) -> Result<ClosestAppointment> {
+        transport
+            .session()
+            .get_closest_appointment(PROCEDURE, AttentionType::InPerson)
+            .await
+            .map(Option::unwrap)
+    }
+
+    #[tokio::test]
+    async fn closest_appointment_skips_days_without_slots() {
+        let transport = closest_transport(PROCEDURE.0, |day| match day {
+            "04/11/2026" => vec!["17:00", "12:00"],
+            "12/11/2026" => vec!["09:00"],
+            _ => vec![],
+        });
+
+        let closest = closest_appointment(&transport).await.unwrap();
+        assert_eq!(closest.office.office_id, 1);
+        assert_eq!(closest.procedure_office_id, ProcedureOfficeId(3210));
+        assert_eq!(closest.date, Some(date(4)));
+        let times: Vec<_> = closest.slots.iter().map(|slot| slot.time.time()).collect();
+        assert_eq!(times, [time(12, 0), time(17, 0)]);
+        assert_eq!(
+            slots_requests(&transport),
+            [
+                ("03/11/2026".to_string(), "false".to_string()),
+                ("04/11/2026".to_string(), "false".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn closest_appointment_queries_only_the_final_day_as_last() {
+        let transport = closest_transport(PROCEDURE.0, |day| match day {
+            "12/11/2026" => vec!["09:00"],
+            _ => vec![],
+        });
+
+        let closest = closest_appointment(&transport).await.unwrap();
+        assert_eq!(closest.date, Some(date(12)));
+        assert_eq!(closest.slots.len(), 1);
+        assert_eq!(
+            slots_requests(&transport),
+            [
+                ("03/11/2026".to_string(), "false".to_string()),
+                ("04/11/2026".to_string(), "false".to_string()),
+                ("12/11/2026".to_string(), "true".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn closest_appointment_without_slots_has_no_date() {
+        let transport = closest_transport(PROCEDURE.0, |_| vec![]);
+
+        let closest = closest_appointment(&transport).await.unwrap();
+        assert_eq!(closest.office.office_id, 1);
+        assert_eq!(closest.date, None);
+        assert!(closest.slots.is_empty());
+        assert_eq!(slots_requests(&transport).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn closest_appointment_fails_without_the_procedure_in_the_office() {
+        let transport = closest_transport(PROCEDURE.0 + 1, |_| vec!["09:00"]);
+
+        let err = closest_appointment(&transport).await.unwrap_err();
+        assert!(matches!(err, Error::UnexpectedPage { .. }), "{:?}", err);
+        assert!(slots_requests(&transport).is_empty());
+    }
+}
@@ -0,0 +1,329 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use reqwest::{
+    ClientBuilder, Proxy, Url,
+    header::{ACCEPT_LANGUAGE, HeaderMap, HeaderValue, USER_AGENT},
+};
+
+use crate::{
+    AppointmentSession, BuildError, Endpoints, RateLimit, RateLimiter, ReqwestTransport,
+    RetryPolicy, Transport,
+};
+
+/// Base URL of the Madrid appointments page.
+pub const DEFAULT_BASE_URL: &str = "https://servpub.madrid.es/GNSIS_WBCIUDADANO/";
+
+/// User agent sent by default on every request.
+pub const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:141.0) Gecko/20100101 Firefox/141.0";
+
+/// Builder for configuring an [`AppointmentSession`].
+///
+/// The timeouts and the proxy only apply to the default reqwest based
+/// transport, and are ignored when a custom transport is given through
+/// [`AppointmentSessionBuilder::transport`].
+pub struct AppointmentSessionBuilder {
+    base_url: Option<Url>,
+    user_agent: String,
+    accept_language: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<Proxy>,
+    retry_policy: RetryPolicy,
+    rate_limit: Option<RateLimit>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    client_builder: Option<ClientBuilder>,
+    transport: Option<Arc<dyn Transport>>,
+    state_file: Option<PathBuf>,
+}
+
+impl Default for AppointmentSessionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppointmentSessionBuilder {
+    pub fn new() -> Self {
+        AppointmentSessionBuilder {
+            base_url: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            accept_language: None,
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            retry_policy: RetryPolicy::default(),
+            rate_limit: None,
+            rate_limiter: None,
+            client_builder: None,
+            transport: None,
+            state_file: None,
+        }
+    }
+
+    /// Sets the base URL all the endpoints are resolved against. Defaults to
+    /// [`DEFAULT_BASE_URL`].
+    pub fn base_url(mut self, base_url: Url) -> Self {
+        self.base_url = Some(base_url);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Sets the value of the Accept-Language header sent on every request.
+    pub fn accept_language(mut self, accept_language: impl Into<String>) -> Self {
+        self.accept_language = Some(accept_language.into());
+        self
+    }
+
+    /// Sets the timeout of each request, from the moment it is sent until the
+    /// response body has been read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Sets the policy for retrying requests that fail because of transient
+    /// errors. Defaults to [`RetryPolicy::default`].
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limits the rate of requests sent by the session, including the ones
+    /// sent for initializing it and the retries. Requests are not limited by
+    /// default.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self.rate_limiter = None;
+        self
+    }
+
+    /// Same as [`AppointmentSessionBuilder::rate_limit`], but taking the
+    /// requests from the given limiter, so the limit is shared with every
+    /// other session built with it.
+    pub fn shared_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self.rate_limit = None;
+        self
+    }
+
+    /// Sets the reqwest client builder the default transport is built from.
+    pub fn client_builder(mut self, client_builder: ClientBuilder) -> Self {
+        self.client_builder = Some(client_builder);
+        self
+    }
+
+    /// Sends all the requests of the session through the given transport
+    /// instead of the default reqwest based one.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Keeps the cookies of the session and whether it is initialized in the
+    /// given file, so the session is restored from it instead of initialized
+    /// again by the next process using the same file.
+    ///
+    /// The file is read when the session is first used, and written every time
+    /// the session is initialized. Failures reading or writing it are logged
+    /// and otherwise ignored.
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    pub fn build(self) -> Result<AppointmentSession, BuildError> {
+        let mut base_url = match self.base_url {
+            Some(base_url) => base_url,
+            None => Url::parse(DEFAULT_BASE_URL).unwrap(),
+        };
+
+        if base_url.cannot_be_a_base() {
+            return Err(BuildError::InvalidBaseUrl(base_url.to_string()));
+        }
+
+        // Without the trailing slash, the last segment of the base URL would
+        // be replaced when joining the endpoints to it.
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+
+        let endpoints = Endpoints::new(base_url.clone())
+            .map_err(|_| BuildError::InvalidBaseUrl(base_url.to_string()))?;
+
+        let rate_limiter = match (self.rate_limiter, self.rate_limit) {
+            (Some(rate_limiter), _) => Some(rate_limiter),
+            (None, Some(rate_limit)) => Some(Arc::new(RateLimiter::new(rate_limit))),
+            (None, None) => None,
+        };
+        if let Some(rate_limiter) = &rate_limiter
+            && !rate_limiter.limit().is_valid()
+        {
+            return Err(BuildError::InvalidRateLimit(rate_limiter.limit()));
+        }
+
+        if !self.retry_policy.is_valid() {
+            return Err(BuildError::InvalidRetryPolicy(self.retry_policy));
+        }
+
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(
+            USER_AGENT,
+            HeaderValue::from_str(&self.user_agent)
+                .map_err(|_| BuildError::InvalidHeader(USER_AGENT))?,
+        );
+
+        if let Some(accept_language) = self.accept_language {
+            default_headers.insert(
+                ACCEPT_LANGUAGE,
+                HeaderValue::from_str(&accept_language)
+                    .map_err(|_| BuildError::InvalidHeader(ACCEPT_LANGUAGE))?,
+            );
+        }
+
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => {
+                let mut cb = self.client_builder.unwrap_or_default();
+                if let Some(timeout) = self.timeout {
+                    cb = cb.timeout(timeout);
+                }
+                if let Some(timeout) = self.connect_timeout {
+                    cb = cb.connect_timeout(timeout);
+                }
+                if let Some(proxy) = self.proxy {
+                    cb = cb.proxy(proxy);
+                }
+
+                Arc::new(ReqwestTransport::new(cb).map_err(BuildError::Client)?)
+            }
+        };
+
+        Ok(AppointmentSession::from_parts(
+            transport,
+            endpoints,
+            default_headers,
+            self.retry_policy,
+            rate_limiter,
+            self.state_file,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ExpectedResponse, TransportRequest,
+        testing::{FakeTransport, response},
+    };
+
+    const OFFICES_PAGE: &str = include_str!("../tests/fixtures/offices.html");
+
+    fn session(transport: &FakeTransport) -> AppointmentSessionBuilder {
+        AppointmentSession::builder()
+            .transport(transport.clone())
+            .retry_policy(RetryPolicy::none())
+    }
+
+    #[tokio::test]
+    async fn base_url_without_trailing_slash_keeps_its_last_segment() {
+        let transport = FakeTransport::serving("/oficina.do", OFFICES_PAGE);
+        let session = session(&transport)
+            .base_url(Url::parse("https://citas.example.com/madrid/citas").unwrap())
+            .build()
+            .unwrap();
+
+        session.list_offices().await.unwrap();
+        assert!(
+            transport
+                .requests()
+                .iter()
+                .all(|request| request.url.host_str() == Some("citas.example.com")),
+        );
+        assert_eq!(
+            transport.paths().last().map(String::as_str),
+            Some("/madrid/citas/oficina.do")
+        );
+    }
+
+    #[test]
+    fn build_rejects_base_url_that_cannot_be_a_base() {
+        let result = AppointmentSession::builder()
+            .base_url(Url::parse("mailto:citas@example.com").unwrap())
+            .build();
+
+        assert!(matches!(result, Err(BuildError::InvalidBaseUrl(_))));
+    }
+
+    #[tokio::test]
+    async fn default_headers_are_sent_unless_set_on_the_request() {
+        let transport = FakeTransport::new(|request| response(&request.url, ""));
+        let session = session(&transport)
+            .user_agent("madrid-cita-previa-test")
+            .accept_language("es-ES")
+            .build()
+            .unwrap();
+
+        let url = session.endpoints().base.clone();
+        session
+            .send_request(
+                "test",
+                TransportRequest::get(url.clone()),
+                ExpectedResponse::Any,
+            )
+            .await
+            .unwrap();
+        session
+            .send_request(
+                "test",
+                TransportRequest::get(url)
+                    .header(USER_AGENT, HeaderValue::from_static("custom-agent")),
+                ExpectedResponse::Any,
+            )
+            .await
+            .unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].headers[USER_AGENT], "madrid-cita-previa-test");
+        assert_eq!(requests[0].headers[ACCEPT_LANGUAGE], "es-ES");
+        assert_eq!(requests[1].headers[USER_AGENT], "custom-agent");
+        assert_eq!(requests[1].headers[ACCEPT_LANGUAGE], "es-ES");
+    }
+
+    #[test]
+    fn build_rejects_invalid_retry_multiplier() {
+        let result = AppointmentSession::builder()
+            .retry_policy(RetryPolicy {
+                multiplier: 0.5,
+                ..Default::default()
+            })
+            .build();
+
+        assert!(matches!(result, Err(BuildError::InvalidRetryPolicy(_))));
+    }
+
+    #[test]
+    fn build_rejects_tiny_rate_limit() {
+        let result = AppointmentSession::builder()
+            .rate_limit(RateLimit::new(1e-20, 1))
+            .build();
+
+        assert!(matches!(result, Err(BuildError::InvalidRateLimit(_))));
+    }
+}
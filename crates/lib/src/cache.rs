@@ -0,0 +1,381 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::persist::{APP_DIR_NAME, write_file_atomically};
+use crate::{
+    AppointmentSession, NetOfficeBasicInfoModel, NetOfficeModel, NetProcedureModel, OfficeId,
+    Result,
+};
+
+/// A value stored in a [`CacheBackend`], along with the moment it was stored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedValue {
+    pub stored_at: SystemTime,
+    pub value: Value,
+}
+
+/// Storage of the responses cached by a [`CachedSession`].
+///
+/// Caching is best effort: backends are expected to handle their own failures,
+/// reporting a missing entry when it can't be read.
+pub trait CacheBackend: Send + Sync {
+    fn get(&self, key: &str) -> Option<CachedValue>;
+    fn set(&self, key: &str, value: CachedValue);
+}
+
+/// A [`CacheBackend`] that keeps the entries in memory, so they only last
+/// while the process runs.
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, CachedValue>>,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    fn get(&self, key: &str) -> Option<CachedValue> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: CachedValue) {
+        self.entries.lock().unwrap().insert(key.to_string(), value);
+    }
+}
+
+/// A [`CacheBackend`] that keeps each entry in a JSON file inside a
+/// directory, so they are shared between runs.
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    /// Creates a cache kept in the given directory, which is created when the
+    /// first entry is stored.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DiskCache { dir: dir.into() }
+    }
+
+    /// Creates a cache kept in the user cache directory, as
+    /// `$XDG_CACHE_HOME/madrid-cita-previa` on Linux. Returns `None` if the
+    /// user cache directory is unknown.
+    pub fn in_user_cache_dir() -> Option<Self> {
+        dirs::cache_dir().map(|dir| Self::new(dir.join(APP_DIR_NAME)))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        let file_name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.dir.join(format!("{}.json", file_name))
+    }
+
+    fn write_entry(&self, path: &Path, value: &CachedValue) -> io::Result<()> {
+        write_file_atomically(path, &serde_json::to_vec(value)?)
+    }
+}
+
+impl CacheBackend for DiskCache {
+    fn get(&self, key: &str) -> Option<CachedValue> {
+        let path = self.entry_path(key);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("Couldn't read cache entry {}: {}", path.display(), err);
+                return None;
+            }
+        };
+
+        serde_json::from_slice(&data)
+            .inspect_err(|err| warn!("Ignoring invalid cache entry {}: {}", path.display(), err))
+            .ok()
+    }
+
+    fn set(&self, key: &str, value: CachedValue) {
+        let path = self.entry_path(key);
+        if let Err(err) = self.write_entry(&path, &value) {
+            warn!("Couldn't write cache entry {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// How long the responses of each operation of a [`CachedSession`] are
+/// cached for.
+#[derive(Debug, Clone)]
+pub struct CacheTtls {
+    pub offices: Duration,
+    pub procedures: Duration,
+    pub office_details: Duration,
+}
+
+impl Default for CacheTtls {
+    fn default() -> Self {
+        CacheTtls {
+            offices: Duration::from_secs(24 * 60 * 60),
+            procedures: Duration::from_secs(24 * 60 * 60),
+            office_details: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Wraps an [`AppointmentSession`], caching the responses of the operations
+/// that return data that rarely changes: the lists of offices and procedures
+/// and the details of each office.
+///
+/// The entries are keyed by the base URL of the session too, so sessions
+/// pointed at different servers can share the same backend.
+///
+/// The rest of the operations are available through [`Deref`], and always
+/// hit the server.
+pub struct CachedSession {
+    session: AppointmentSession,
+    backend: Box<dyn CacheBackend>,
+    ttls: CacheTtls,
+    key_prefix: String,
+}
+
+impl Deref for CachedSession {
+    type Target = AppointmentSession;
+
+    fn deref(&self) -> &Self::Target {
+        &self.session
+    }
+}
+
+impl CachedSession {
+    pub fn new(session: AppointmentSession, backend: impl CacheBackend + 'static) -> Self {
+        CachedSession {
+            key_prefix: format!("{:016x}", hash_url(&session.endpoints().base)),
+            session,
+            backend: Box::new(backend),
+            ttls: CacheTtls::default(),
+        }
+    }
+
+    pub fn with_ttls(mut self, ttls: CacheTtls) -> Self {
+        self.ttls = ttls;
+        self
+    }
+
+    pub fn session(&self) -> &AppointmentSession {
+        &self.session
+    }
+
+    pub fn into_session(self) -> AppointmentSession {
+        self.session
+    }
+
+    pub async fn list_offices(&self) -> Result<Vec<NetOfficeBasicInfoModel>> {
+        self.cached(
+            "offices",
+            self.ttls.offices,
+            self.session.list_offices(),
+            |_| true,
+        )
+        .await
+    }
+
+    pub async fn list_available_procedures(&self) -> Result<Vec<NetProcedureModel>> {
+        self.cached(
+            "procedures",
+            self.ttls.procedures,
+            self.session.list_available_procedures(),
+            |_| true,
+        )
+        .await
+    }
+
+    pub async fn get_office_details(&self, office_id: OfficeId) -> Result<Option<NetOfficeModel>> {
+        self.cached(
+            &format!("office_details_{}", office_id.0),
+            self.ttls.office_details,
+            self.session.get_office_details(office_id),
+            // An unknown office may just not have been published yet.
+            Option::is_some,
+        )
+        .await
+    }
+
+    /// Returns the value cached under the given key if it hasn't expired, or
+    /// awaits the given request and caches its result otherwise, if
+    /// `cacheable` accepts it. Failed requests are not cached.
+    async fn cached<T: Serialize + DeserializeOwned>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        request: impl Future<Output = Result<T>>,
+        cacheable: impl FnOnce(&T) -> bool,
+    ) -> Result<T> {
+        let key = &format!("{}_{}", self.key_prefix, key);
+        if let Some(cached) = self.backend.get(key) {
+            let fresh = cached
+                .stored_at
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed < ttl);
+            if fresh {
+                match serde_json::from_value(cached.value) {
+                    Ok(value) => {
+                        debug!("Cache hit: {}", key);
+                        return Ok(value);
+                    }
+                    Err(err) => warn!("Ignoring invalid cached value for {}: {}", key, err),
+                }
+            }
+        }
+
+        debug!("Cache miss: {}", key);
+        let value = request.await?;
+        if !cacheable(&value) {
+            return Ok(value);
+        }
+
+        match serde_json::to_value(&value) {
+            Ok(json) => self.backend.set(
+                key,
+                CachedValue {
+                    stored_at: SystemTime::now(),
+                    value: json,
+                },
+            ),
+            Err(err) => warn!("Couldn't cache value for {}: {}", key, err),
+        }
+        Ok(value)
+    }
+}
+
+/// Hashes the given URL with 64-bit FNV-1a, which unlike the hasher of the
+/// standard library is guaranteed to give the same result in every run.
+fn hash_url(url: &Url) -> u64 {
+    url.as_str().bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeTransport, TestDir};
+
+    const OFFICES_PAGE: &str = include_str!("../tests/fixtures/offices.html");
+
+    fn offices_transport() -> FakeTransport {
+        FakeTransport::serving("/oficina.do", OFFICES_PAGE)
+    }
+
+    fn office_requests(transport: &FakeTransport) -> usize {
+        transport
+            .paths()
+            .iter()
+            .filter(|path| path.ends_with("/oficina.do") || path.ends_with("/dameOficina.do"))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn entries_are_kept_per_base_url() {
+        let dir = TestDir::new("cache-base-url");
+        let production = offices_transport();
+        let stand_in = offices_transport();
+        let stand_in_session = || {
+            AppointmentSession::builder()
+                .base_url(Url::parse("http://localhost:8080/GNSIS_WBCIUDADANO/").unwrap())
+                .transport(stand_in.clone())
+                .build()
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            CachedSession::new(production.session(), DiskCache::new(dir.path()))
+                .list_offices()
+                .await
+                .unwrap();
+            CachedSession::new(stand_in_session(), DiskCache::new(dir.path()))
+                .list_offices()
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(office_requests(&production), 1);
+        assert_eq!(office_requests(&stand_in), 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_offices_are_not_cached() {
+        let transport = FakeTransport::with_bodies(|_| r#"{"idOficina": 0}"#.to_string());
+        let session = CachedSession::new(transport.session(), MemoryCache::new());
+
+        for _ in 0..2 {
+            let details = session.get_office_details(OfficeId(1)).await.unwrap();
+            assert!(details.is_none());
+        }
+        assert_eq!(office_requests(&transport), 2);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_fetched_again() {
+        let transport = offices_transport();
+        let session =
+            CachedSession::new(transport.session(), MemoryCache::new()).with_ttls(CacheTtls {
+                offices: Duration::ZERO,
+                ..Default::default()
+            });
+
+        for _ in 0..2 {
+            assert_eq!(session.list_offices().await.unwrap().len(), 4);
+        }
+        assert_eq!(office_requests(&transport), 2);
+    }
+
+    #[tokio::test]
+    async fn expired_or_corrupt_disk_entries_are_fetched_again() {
+        let dir = TestDir::new("cache-invalid-entries");
+        let transport = offices_transport();
+        let list_offices = || async {
+            CachedSession::new(transport.session(), DiskCache::new(dir.path()))
+                .list_offices()
+                .await
+                .unwrap()
+        };
+
+        list_offices().await;
+        let entries: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+
+        let mut expired: CachedValue = serde_json::from_slice(&fs::read(entry).unwrap()).unwrap();
+        expired.stored_at = SystemTime::UNIX_EPOCH;
+        fs::write(entry, serde_json::to_vec(&expired).unwrap()).unwrap();
+        assert_eq!(list_offices().await.len(), 4);
+        assert_eq!(office_requests(&transport), 2);
+
+        fs::write(entry, "{not json").unwrap();
+        assert_eq!(list_offices().await.len(), 4);
+        assert_eq!(office_requests(&transport), 3);
+
+        // The entry fetched again is cached as usual.
+        assert_eq!(list_offices().await.len(), 4);
+        assert_eq!(office_requests(&transport), 3);
+    }
+}
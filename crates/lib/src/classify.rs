@@ -0,0 +1,364 @@
+use std::{fmt, time::Duration};
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::{StatusCode, header::RETRY_AFTER};
+use scraper::{Html, Node, Selector};
+
+use crate::TransportResponse;
+
+/// Why the server is not serving the requested page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnavailableReason {
+    /// The service is down for maintenance.
+    Maintenance,
+    /// The server answered with a generic error page.
+    ErrorPage,
+    /// The request was blocked, or a captcha has to be solved before going
+    /// on.
+    Blocked,
+}
+
+impl fmt::Display for UnavailableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnavailableReason::Maintenance => write!(f, "service under maintenance"),
+            UnavailableReason::ErrorPage => write!(f, "server error page"),
+            UnavailableReason::Blocked => write!(f, "request blocked or captcha required"),
+        }
+    }
+}
+
+/// A response recognised as the server not serving the requested page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Unavailable {
+    pub reason: UnavailableReason,
+    /// How long the server asks to wait before trying again, if it says so.
+    pub retry_after: Option<Duration>,
+}
+
+lazy_static! {
+    static ref SELECTOR_TITLE: Selector = Selector::parse("title").unwrap();
+    static ref SELECTOR_META_HTTP_EQUIV: Selector =
+        Selector::parse("meta[http-equiv][content]").unwrap();
+    static ref RE_RETRY_IN: Regex =
+        Regex::new(r"(?i)\ben\s+(\d+)\s+(segundos?|minutos?|horas?)\b").unwrap();
+}
+
+/// Elements whose contents are never shown to the user.
+const HIDDEN_ELEMENTS: &[&str] = &["script", "style", "template", "noscript"];
+
+/// Phrases only found in the pages served while the service is down for
+/// maintenance.
+const MAINTENANCE_MARKERS: &[&str] = &[
+    "en mantenimiento",
+    "tareas de mantenimiento",
+    "labores de mantenimiento",
+    "servicio no disponible",
+    "temporalmente no disponible",
+    "fuera de servicio",
+];
+
+/// Phrases found in captcha challenges and in the pages served by the web
+/// application firewall when rejecting a request. A bare "captcha" is only
+/// looked for in the title, since regular pages may embed a captcha widget.
+const BLOCKED_MARKERS: &[&str] = &[
+    "resuelva el captcha",
+    "complete el captcha",
+    "the requested url was rejected",
+    "request rejected",
+    "access denied",
+    "acceso denegado",
+];
+
+/// Phrases found in the generic error pages of the server.
+const ERROR_PAGE_MARKERS: &[&str] = &[
+    "se ha producido un error",
+    "ha ocurrido un error",
+    "error interno",
+    "internal server error",
+];
+
+/// Checks whether a response is a maintenance, error or blocking page instead
+/// of the page served by the requested endpoint.
+///
+/// Only HTML bodies are inspected, since the pages looked for are never served
+/// as JSON. The phrases are only looked for in the text shown to the user, as
+/// regular pages embed some of them in their scripts for the error messages
+/// shown on demand. Even so, a regular page mentioning one of them in the
+/// name of a procedure or office would be mistaken with an unavailable one.
+///
+/// A 503 response is only taken as maintenance if its body or its
+/// Retry-After header says so, since load balancers answer with it for
+/// transient failures too. Generic error pages are only looked for in
+/// successful and server error responses, so client errors such as a 404 are
+/// reported by their status.
+pub(crate) fn classify_response(resp: &TransportResponse) -> Option<Unavailable> {
+    let page = is_html(&resp.body).then(|| Page::parse(&resp.body));
+    let reason = classify_reason(resp, page.as_ref())?;
+    Some(Unavailable {
+        reason,
+        retry_after: retry_after(resp, page.as_ref()),
+    })
+}
+
+/// The parts of an HTML page inspected to classify it, in lowercase.
+struct Page {
+    text: String,
+    title: String,
+    /// Delay of the refresh meta element of the page, if any.
+    refresh: Option<u64>,
+}
+
+impl Page {
+    fn parse(body: &str) -> Self {
+        let html = Html::parse_document(body);
+        let title = html
+            .root_element()
+            .select(&SELECTOR_TITLE)
+            .next()
+            .map(|title| title.text().collect::<String>().trim().to_lowercase())
+            .unwrap_or_default();
+        let refresh = html
+            .root_element()
+            .select(&SELECTOR_META_HTTP_EQUIV)
+            .filter(|meta| {
+                meta.value()
+                    .attr("http-equiv")
+                    .is_some_and(|value| value.trim().eq_ignore_ascii_case("refresh"))
+            })
+            .find_map(|meta| refresh_delay(meta.value().attr("content")?));
+        Page {
+            text: visible_text(&html).to_lowercase(),
+            title,
+            refresh,
+        }
+    }
+}
+
+fn classify_reason(resp: &TransportResponse, page: Option<&Page>) -> Option<UnavailableReason> {
+    if resp.status == StatusCode::SERVICE_UNAVAILABLE && resp.headers.contains_key(RETRY_AFTER) {
+        return Some(UnavailableReason::Maintenance);
+    }
+
+    let Page { text, title, .. } = page?;
+    let may_be_error_page = resp.status.is_success() || resp.status.is_server_error();
+
+    if contains_any(text, MAINTENANCE_MARKERS) || title.contains("mantenimiento") {
+        Some(UnavailableReason::Maintenance)
+    } else if contains_any(text, BLOCKED_MARKERS) || title.contains("captcha") {
+        Some(UnavailableReason::Blocked)
+    } else if may_be_error_page
+        && (contains_any(text, ERROR_PAGE_MARKERS) || title.contains("error"))
+    {
+        Some(UnavailableReason::ErrorPage)
+    } else {
+        None
+    }
+}
+
+/// Returns the text of the page, leaving out the contents of the elements
+/// never shown to the user, as scripts.
+fn visible_text(html: &Html) -> String {
+    let mut text = String::new();
+    for node in html.tree.root().descendants() {
+        let Node::Text(node_text) = node.value() else {
+            continue;
+        };
+
+        let hidden = node.ancestors().any(|ancestor| {
+            ancestor
+                .value()
+                .as_element()
+                .is_some_and(|element| HIDDEN_ELEMENTS.contains(&element.name()))
+        });
+        if !hidden {
+            text.push_str(node_text);
+            text.push(' ');
+        }
+    }
+    text
+}
+
+/// Reads the delay from the content of a refresh meta element, as in
+/// `30; url=https://...`.
+fn refresh_delay(content: &str) -> Option<u64> {
+    content.split([';', ',']).next()?.trim().parse::<u64>().ok()
+}
+
+fn is_html(body: &str) -> bool {
+    let start = body.trim_start();
+    start.starts_with('<') && !start.starts_with("<?xml")
+}
+
+fn contains_any(body: &str, markers: &[&str]) -> bool {
+    markers.iter().any(|marker| body.contains(marker))
+}
+
+/// Reads how long to wait before trying again from the Retry-After header, or
+/// failing that, from a refresh meta tag or a sentence like "inténtelo de
+/// nuevo en 10 minutos" in the text shown by the page.
+fn retry_after(resp: &TransportResponse, page: Option<&Page>) -> Option<Duration> {
+    if let Some(retry_after) = retry_after_header(resp) {
+        return Some(retry_after);
+    }
+
+    let page = page?;
+    if let Some(secs) = page.refresh {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let caps = RE_RETRY_IN.captures(&page.text)?;
+    let amount = caps[1].parse::<u64>().ok()?;
+    let unit = &caps[2];
+    // The amount comes from the page, so a hint too large to be taken
+    // seriously is dropped rather than overflowing.
+    let secs = if unit.starts_with("hora") {
+        amount.checked_mul(3600)?
+    } else if unit.starts_with("minuto") {
+        amount.checked_mul(60)?
+    } else {
+        amount
+    };
+    Some(Duration::from_secs(secs))
+}
+
+/// Reads how long to wait before trying again from the Retry-After header of
+/// the response, given either in seconds or as a date.
+pub(crate) fn retry_after_header(resp: &TransportResponse) -> Option<Duration> {
+    let value = resp.headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{
+        Url,
+        header::{HeaderMap, HeaderValue},
+    };
+
+    use super::*;
+
+    const OFFICES_PAGE: &str = include_str!("../tests/fixtures/offices.html");
+    const MAINTENANCE_PAGE: &str = include_str!("../tests/fixtures/maintenance.html");
+
+    fn response(status: StatusCode, headers: HeaderMap, body: &str) -> TransportResponse {
+        TransportResponse {
+            status,
+            url: Url::parse("https://servpub.madrid.es/GNSIS_WBCIUDADANO/oficina.do").unwrap(),
+            headers,
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn regular_page_with_error_messages_in_scripts_is_available() {
+        let resp = response(StatusCode::OK, HeaderMap::new(), OFFICES_PAGE);
+        assert_eq!(classify_response(&resp), None);
+    }
+
+    #[test]
+    fn maintenance_page_is_detected_with_retry_hint() {
+        let resp = response(StatusCode::OK, HeaderMap::new(), MAINTENANCE_PAGE);
+        assert_eq!(
+            classify_response(&resp),
+            Some(Unavailable {
+                reason: UnavailableReason::Maintenance,
+                retry_after: Some(Duration::from_secs(30 * 60)),
+            })
+        );
+    }
+
+    #[test]
+    fn error_page_is_detected() {
+        let body = "<html><head><title>Error</title></head>\
+            <body><p>Se ha producido un error al procesar su solicitud.</p></body></html>";
+        let resp = response(StatusCode::OK, HeaderMap::new(), body);
+        assert_eq!(
+            classify_response(&resp).map(|unavailable| unavailable.reason),
+            Some(UnavailableReason::ErrorPage)
+        );
+    }
+
+    #[test]
+    fn not_found_page_is_left_as_http_status() {
+        let body = "<html><head><title>Error 404</title></head>\
+            <body><h1>Error 404</h1><p>Se ha producido un error.</p></body></html>";
+        let resp = response(StatusCode::NOT_FOUND, HeaderMap::new(), body);
+        assert_eq!(classify_response(&resp), None);
+    }
+
+    #[test]
+    fn retry_hint_in_scripts_is_ignored() {
+        let body = "<html><head><title>Error</title>\
+            <script>alert('Inténtelo de nuevo en 5 minutos.');</script></head>\
+            <body><p>Se ha producido un error al procesar su solicitud.</p></body></html>";
+        let resp = response(StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), body);
+        assert_eq!(
+            classify_response(&resp),
+            Some(Unavailable {
+                reason: UnavailableReason::ErrorPage,
+                retry_after: None,
+            })
+        );
+    }
+
+    #[test]
+    fn refresh_meta_element_is_read_as_retry_hint() {
+        let body = "<html><head><title>Mantenimiento</title>\
+            <!-- <meta http-equiv=\"refresh\" content=\"5\"> -->\
+            <script>document.write('<meta http-equiv=\"refresh\" content=\"10\">');</script>\
+            <meta http-equiv=\"Refresh\" content=\"300; url=/GNSIS_WBCIUDADANO/\"></head>\
+            <body><p>Servicio en mantenimiento.</p></body></html>";
+        let resp = response(StatusCode::OK, HeaderMap::new(), body);
+        assert_eq!(
+            classify_response(&resp).and_then(|unavailable| unavailable.retry_after),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn overflowing_retry_hint_is_dropped() {
+        let body = "<html><body><p>Servicio en mantenimiento. \
+            Inténtelo de nuevo en 18446744073709551615 horas.</p></body></html>";
+        let resp = response(StatusCode::OK, HeaderMap::new(), body);
+        assert_eq!(
+            classify_response(&resp),
+            Some(Unavailable {
+                reason: UnavailableReason::Maintenance,
+                retry_after: None,
+            })
+        );
+    }
+
+    #[test]
+    fn plain_service_unavailable_is_left_as_http_status() {
+        let body = "<html><head><title>503 Service Temporarily Unavailable</title></head>\
+            <body><center><h1>503 Service Temporarily Unavailable</h1></center></body></html>";
+        let resp = response(StatusCode::SERVICE_UNAVAILABLE, HeaderMap::new(), body);
+        assert_eq!(classify_response(&resp), None);
+    }
+
+    #[test]
+    fn service_unavailable_with_retry_after_is_maintenance() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        let resp = response(StatusCode::SERVICE_UNAVAILABLE, headers, "");
+        assert_eq!(
+            classify_response(&resp),
+            Some(Unavailable {
+                reason: UnavailableReason::Maintenance,
+                retry_after: Some(Duration::from_secs(120)),
+            })
+        );
+    }
+}
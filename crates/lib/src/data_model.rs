@@ -0,0 +1,405 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::persist::{APP_DIR_NAME, write_file_atomically};
+use crate::{
+    AppointmentSession, Coordinates, DataGenModel, DataGenOffice, DataGenOfficeProcedure,
+    DataGenProcedure, DataModelError, Error, NetOfficeBasicInfoModel, OfficeId, ProcedureId,
+    StaticOffice, StaticProcedure,
+};
+
+/// Options for downloading a [`DataModel`] with
+/// [`AppointmentSession::download_data_model`].
+#[derive(Debug, Clone, Default)]
+pub struct DataModelDownloadOptions {
+    /// Download only the offices in this group.
+    pub filter_group: Option<String>,
+    /// Download only the offices whose name contains this string.
+    pub filter_name: Option<String>,
+    /// Download also the information page of each office.
+    pub extended_info: bool,
+}
+
+/// The offices and procedures available in the appointments page, loaded at
+/// runtime.
+///
+/// Offers the same data as the model compiled into the data crate, but can be
+/// read from a `model.json` generated by the datagen binary, so it can be
+/// updated without rebuilding.
+#[derive(Debug, Clone)]
+pub struct DataModel {
+    offices: Vec<DataGenOffice>,
+    procedures: Vec<DataGenProcedure>,
+}
+
+impl From<DataGenModel> for DataModel {
+    fn from(model: DataGenModel) -> Self {
+        DataModel {
+            offices: model.offices,
+            procedures: model.procedures,
+        }
+    }
+}
+
+impl DataModel {
+    pub fn from_json(json: &str) -> Result<Self, DataModelError> {
+        Ok(serde_json::from_str::<DataGenModel>(json)?.into())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, DataModelError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Writes the model to the given file, creating its directory if needed.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), DataModelError> {
+        write_file_atomically(path.as_ref(), self.to_json()?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns where the model is kept in the user data directory, as
+    /// `$XDG_DATA_HOME/madrid-cita-previa/model.json` on Linux. Returns `None`
+    /// if the user data directory is unknown.
+    pub fn user_data_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join(APP_DIR_NAME).join("model.json"))
+    }
+
+    /// Builds the model from the offices and procedures compiled into the
+    /// data crate.
+    pub fn from_static(offices: &[&StaticOffice], procedures: &[&StaticProcedure]) -> Self {
+        DataModel {
+            offices: offices
+                .iter()
+                .map(|office| DataGenOffice {
+                    name: office.name.to_string(),
+                    group: office.group.to_string(),
+                    id: office.id,
+                    procedures: office
+                        .procedures
+                        .iter()
+                        .map(|proc| DataGenOfficeProcedure {
+                            procedure_name: proc.procedure_name.to_string(),
+                            procedure_category: proc.procedure_category.to_string(),
+                            procedure_office_id: proc.procedure_office_id,
+                            procedure_id: proc.procedure_id,
+                        })
+                        .collect(),
+                    coordinates: office.coordinates,
+                    address: office.address.to_string(),
+                    district_code: office.district_code.to_string(),
+                    district_name: office.district_name.to_string(),
+                    office_code: office.office_code.map(str::to_string),
+                    url: office.url.to_string(),
+                    extended_info: None,
+                })
+                .collect(),
+            procedures: procedures
+                .iter()
+                .map(|proc| DataGenProcedure {
+                    procedure_category: proc.procedure_category.to_string(),
+                    procedure_name: proc.procedure_name.to_string(),
+                    procedure_id: proc.procedure_id,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, DataModelError> {
+        Ok(serde_json::to_string(&DataGenModel {
+            offices: self.offices.clone(),
+            procedures: self.procedures.clone(),
+        })?)
+    }
+
+    pub fn into_model(self) -> DataGenModel {
+        DataGenModel {
+            offices: self.offices,
+            procedures: self.procedures,
+        }
+    }
+
+    pub fn offices(&self) -> &[DataGenOffice] {
+        &self.offices
+    }
+
+    pub fn procedures(&self) -> &[DataGenProcedure] {
+        &self.procedures
+    }
+
+    pub fn office(&self, id: OfficeId) -> Option<&DataGenOffice> {
+        self.offices.iter().find(|office| office.id == id)
+    }
+
+    pub fn procedure(&self, id: ProcedureId) -> Option<&DataGenProcedure> {
+        self.procedures.iter().find(|proc| proc.procedure_id == id)
+    }
+
+    /// Returns the offices where the given procedure is available, along
+    /// with the procedure as available in each of them.
+    pub fn offices_with_procedure(
+        &self,
+        id: ProcedureId,
+    ) -> impl Iterator<Item = (&DataGenOffice, &DataGenOfficeProcedure)> {
+        self.offices.iter().filter_map(move |office| {
+            office
+                .procedures
+                .iter()
+                .find(|proc| proc.procedure_id == id)
+                .map(|proc| (office, proc))
+        })
+    }
+}
+
+/// Keeps only the offices in the given group and whose name contains the given
+/// string, if any.
+pub fn filter_relevant_offices(
+    offices: &mut Vec<NetOfficeBasicInfoModel>,
+    group_filter: Option<&str>,
+    name_filter: Option<&str>,
+) {
+    if let Some(group) = group_filter {
+        let group = group.to_lowercase();
+        offices.retain(|office| {
+            let keep = office.group.to_lowercase() == group;
+            if !keep {
+                info!("Filtering office out: {}", office.name);
+            }
+
+            keep
+        });
+    }
+
+    if let Some(name) = name_filter {
+        let name = name.to_lowercase();
+        offices.retain(|office| {
+            let keep = office.name.to_lowercase().contains(&name);
+            if !keep {
+                info!("Filtering office out: {}", office.name);
+            }
+            keep
+        });
+    }
+}
+
+impl AppointmentSession {
+    /// Downloads the offices and procedures available in the appointments
+    /// page, along with the details of each office.
+    ///
+    /// Sends a request per office, so it may take a while. Failures
+    /// downloading the information page of an office are logged and
+    /// otherwise ignored.
+    pub async fn download_data_model(
+        &self,
+        options: &DataModelDownloadOptions,
+    ) -> crate::Result<DataModel> {
+        info!("Listing offices...");
+        let mut offices = self.list_offices().await?;
+        info!("Listing procedures...");
+        let procs = self.list_available_procedures().await?;
+
+        filter_relevant_offices(
+            &mut offices,
+            options.filter_group.as_deref(),
+            options.filter_name.as_deref(),
+        );
+
+        let mut model_offices = Vec::new();
+        for office_basic in offices.into_iter() {
+            info!("Downloading office info: {}", &office_basic.name);
+            let Some(office) = self.get_office_details(office_basic.id).await? else {
+                return Err(Error::unexpected_page(
+                    &self.endpoints().office_info,
+                    format!(
+                        "Couldn't download office info for office {:?}",
+                        &office_basic
+                    ),
+                    "",
+                ));
+            };
+
+            let mut extended_info = None;
+            if options.extended_info && office.url.trim().is_empty() {
+                info!("Office has no information page: {}", &office_basic.name);
+            } else if options.extended_info {
+                info!("Downloading office extended info: {}", &office_basic.name);
+                match self.get_office_extended_info(&office.url).await {
+                    Ok(info) => extended_info = Some(info),
+                    Err(err) => warn!(
+                        "Couldn't download extended info for office {}: {}",
+                        &office_basic.name, err
+                    ),
+                }
+            }
+
+            model_offices.push(DataGenOffice {
+                name: office.name,
+                group: office_basic.group,
+                id: office_basic.id,
+                procedures: office
+                    .procedures
+                    .into_iter()
+                    .map(|proc| DataGenOfficeProcedure {
+                        procedure_name: proc.name,
+                        procedure_category: proc.category,
+                        procedure_office_id: proc.office_procedure_id,
+                        procedure_id: proc.procedure_id,
+                    })
+                    .collect(),
+                coordinates: Some(Coordinates::new(office.latitude, office.longitude)),
+                address: office.address,
+                district_code: office.district_code,
+                district_name: office.district_name,
+                office_code: office.office_code,
+                url: office.url,
+                extended_info,
+            });
+        }
+
+        Ok(DataModel {
+            offices: model_offices,
+            procedures: procs
+                .into_iter()
+                .map(|proc| DataGenProcedure {
+                    procedure_category: proc.procedure_category,
+                    procedure_name: proc.procedure_name,
+                    procedure_id: proc.procedure_id,
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeTransport;
+
+    const MODEL: &str = include_str!("../tests/fixtures/model.json");
+    const OFFICES_PAGE: &str = include_str!("../tests/fixtures/offices.html");
+
+    fn office_ids<'a>(offices: impl Iterator<Item = &'a DataGenOffice>) -> Vec<u32> {
+        offices.map(|office| office.id.0).collect()
+    }
+
+    #[test]
+    fn json_round_trip_keeps_the_model() {
+        let model = DataModel::from_json(MODEL).unwrap();
+        let json = model.to_json().unwrap();
+        let reloaded = DataModel::from_json(&json).unwrap();
+
+        assert_eq!(reloaded.to_json().unwrap(), json);
+        assert_eq!(office_ids(reloaded.offices().iter()), [1, 2]);
+        assert_eq!(reloaded.procedures().len(), 2);
+
+        let office = reloaded.office(OfficeId(1)).unwrap();
+        assert_eq!(office.coordinates, Some(Coordinates::new(40.4153, -3.7074)));
+        assert_eq!(office.address, "Calle Mayor, 72");
+        assert_eq!(office.district_name, "Centro");
+        assert_eq!(office.office_code.as_deref(), Some("LMC"));
+        assert_eq!(office.url, "https://www.madrid.es/oficina-centro");
+        assert!(office.extended_info.is_none());
+        assert_eq!(
+            reloaded.procedure(ProcedureId(322)).unwrap().procedure_name,
+            "Certificado de empadronamiento"
+        );
+    }
+
+    #[test]
+    fn model_without_office_details_is_loaded() {
+        // As generated before the location, address and district of the
+        // offices were kept.
+        let json = r#"{
+            "offices": [{
+                "name": "Línea Madrid Centro",
+                "group": "LINEA MADRID",
+                "id": 1,
+                "procedures": [{
+                    "procedure_name": "Empadronamiento",
+                    "procedure_category": "Padrón",
+                    "procedure_office_id": 1290,
+                    "procedure_id": 321
+                }]
+            }],
+            "procedures": [{
+                "procedure_category": "Padrón",
+                "procedure_name": "Empadronamiento",
+                "procedure_id": 321
+            }]
+        }"#;
+
+        let model = DataModel::from_json(json).unwrap();
+        let office = model.office(OfficeId(1)).unwrap();
+        assert_eq!(office.coordinates, None);
+        assert_eq!(office.address, "");
+        assert_eq!(office.district_code, "");
+        assert_eq!(office.office_code, None);
+        assert_eq!(office.url, "");
+        assert!(office.extended_info.is_none());
+    }
+
+    #[test]
+    fn offices_with_procedure_are_found() {
+        let model = DataModel::from_json(MODEL).unwrap();
+
+        let offices: Vec<_> = model
+            .offices_with_procedure(ProcedureId(322))
+            .map(|(office, proc)| (office.id.0, proc.procedure_office_id.0))
+            .collect();
+        assert_eq!(offices, [(1, 1291), (2, 2291)]);
+
+        let offices: Vec<_> = model.offices_with_procedure(ProcedureId(321)).collect();
+        assert_eq!(office_ids(offices.iter().map(|(office, _)| *office)), [1]);
+
+        assert_eq!(model.offices_with_procedure(ProcedureId(999)).count(), 0);
+    }
+
+    #[tokio::test]
+    async fn offices_without_information_page_are_not_fetched() {
+        let transport = FakeTransport::with_bodies(|path| {
+            if path.ends_with("/oficina.do") {
+                OFFICES_PAGE.to_string()
+            } else if path.ends_with("/tramite.do") {
+                r#"<html><body><select id="selectTramites">
+                    <optgroup label="Padrón"><option value="321">Empadronamiento</option></optgroup>
+                </select></body></html>"#
+                    .to_string()
+            } else if path.ends_with("/dameOficina.do") {
+                r#"{
+                    "idOficina": 1,
+                    "codIntegracion": null,
+                    "latitud": 40.4153,
+                    "longitud": -3.7074,
+                    "nombreOficina": "Línea Madrid Centro",
+                    "direccion": "Calle Mayor, 72",
+                    "codigoDistrito": "01",
+                    "nombreDistrito": "Centro",
+                    "urlInformacion": "",
+                    "tramites": []
+                }"#
+                .to_string()
+            } else {
+                String::new()
+            }
+        });
+        let options = DataModelDownloadOptions {
+            filter_name: Some("Centro".to_string()),
+            extended_info: true,
+            ..Default::default()
+        };
+
+        let model = transport
+            .session()
+            .download_data_model(&options)
+            .await
+            .unwrap();
+        assert_eq!(office_ids(model.offices().iter()), [1]);
+        assert!(model.offices()[0].extended_info.is_none());
+        assert_eq!(
+            transport.paths().last().map(String::as_str),
+            Some("/GNSIS_WBCIUDADANO/dameOficina.do")
+        );
+    }
+}
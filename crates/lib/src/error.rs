@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use reqwest::{StatusCode, Url, header::HeaderName};
+
+use crate::{RateLimit, RetryPolicy, TransportError, UnavailableReason};
+
+/// Maximum number of characters of a response body kept in an [`Error`].
+const MAX_ERROR_BODY_LEN: usize = 512;
+
+/// Errors returned by the library when querying the appointments page.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The request couldn't be sent, or the response couldn't be read (DNS
+    /// errors, connection resets, timeouts...).
+    #[error("Transport error on request to {url}: {source}")]
+    Transport {
+        url: String,
+        #[source]
+        source: TransportError,
+    },
+
+    /// The server answered with a non-successful status code.
+    #[error("Unexpected HTTP status {status} from {url}{}", fmt_retry_after(.retry_after))]
+    HttpStatus {
+        url: String,
+        status: StatusCode,
+        /// How long the server asked to wait before trying again, as given
+        /// by the Retry-After header of 429 and 503 responses.
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
+    /// The server answered with a maintenance, error or blocking page instead
+    /// of the requested one.
+    #[error("Service unavailable on request to {url}: {reason}{}", fmt_retry_after(.retry_after))]
+    ServiceUnavailable {
+        url: String,
+        reason: UnavailableReason,
+        /// How long the server asked to wait before trying again, if it did.
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
+    /// The anonymous session is no longer valid on the server side.
+    #[error("Session expired on request to {url}")]
+    SessionExpired { url: String, body: String },
+
+    /// The server answered with a page that doesn't look like the expected
+    /// one, usually because the layout of the page has changed.
+    #[error("Unexpected page returned by {url}: {reason}")]
+    UnexpectedPage {
+        url: String,
+        reason: String,
+        body: String,
+    },
+
+    /// The response had the expected shape, but its contents couldn't be
+    /// parsed.
+    #[error("Couldn't parse response from {url}: {reason}")]
+    Parse {
+        url: String,
+        reason: String,
+        body: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Returns the URL of the endpoint that caused the error.
+    pub fn url(&self) -> &str {
+        match self {
+            Error::Transport { url, .. }
+            | Error::HttpStatus { url, .. }
+            | Error::ServiceUnavailable { url, .. }
+            | Error::SessionExpired { url, .. }
+            | Error::UnexpectedPage { url, .. }
+            | Error::Parse { url, .. } => url,
+        }
+    }
+
+    /// Returns the (truncated) response body attached to the error, if any.
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            Error::Transport { .. } => None,
+            Error::HttpStatus { body, .. }
+            | Error::ServiceUnavailable { body, .. }
+            | Error::SessionExpired { body, .. }
+            | Error::UnexpectedPage { body, .. }
+            | Error::Parse { body, .. } => Some(body),
+        }
+    }
+
+    /// Returns whether the error is likely to be transient, so the failed
+    /// request may succeed if sent again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport { .. } => true,
+            Error::HttpStatus { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            // Maintenance and blocking pages last longer than any reasonable
+            // backoff, so it's up to the caller to wait for them to go away.
+            Error::ServiceUnavailable { reason, .. } => *reason == UnavailableReason::ErrorPage,
+            Error::SessionExpired { .. } | Error::UnexpectedPage { .. } | Error::Parse { .. } => {
+                false
+            }
+        }
+    }
+
+    /// Returns how long the server asked to wait before trying again, if the
+    /// error was caused by the service being unavailable or throttling the
+    /// requests and the server said so.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::HttpStatus { retry_after, .. }
+            | Error::ServiceUnavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub(crate) fn unexpected_page(url: &Url, reason: impl Into<String>, body: &str) -> Self {
+        Error::UnexpectedPage {
+            url: url.to_string(),
+            reason: reason.into(),
+            body: truncate_body(body),
+        }
+    }
+
+    pub(crate) fn parse(url: &Url, reason: impl ToString, body: &str) -> Self {
+        Error::Parse {
+            url: url.to_string(),
+            reason: reason.to_string(),
+            body: truncate_body(body),
+        }
+    }
+}
+
+fn fmt_retry_after(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(retry_after) => format!(" (retry after {}s)", retry_after.as_secs()),
+        None => String::new(),
+    }
+}
+
+/// Truncates a response body so it can be safely attached to an error.
+pub(crate) fn truncate_body(body: &str) -> String {
+    match body.char_indices().nth(MAX_ERROR_BODY_LEN) {
+        Some((idx, _)) => format!("{}...", &body[..idx]),
+        None => body.to_string(),
+    }
+}
+
+/// Errors returned when building an
+/// [`AppointmentSession`](crate::AppointmentSession).
+#[derive(thiserror::Error, Debug)]
+pub enum BuildError {
+    #[error("Invalid base URL: {0}")]
+    InvalidBaseUrl(String),
+
+    #[error("Invalid value for header {0}")]
+    InvalidHeader(HeaderName),
+
+    #[error("Invalid rate limit, the rate must be finite and at least one request per day: {0:?}")]
+    InvalidRateLimit(RateLimit),
+
+    #[error("Invalid retry policy, the multiplier must be finite and at least 1: {0:?}")]
+    InvalidRetryPolicy(RetryPolicy),
+
+    #[error("Couldn't build HTTP client: {0}")]
+    Client(#[source] reqwest::Error),
+}
+
+/// Errors returned when saving or restoring the state of an
+/// [`AppointmentSession`](crate::AppointmentSession).
+#[derive(thiserror::Error, Debug)]
+pub enum PersistError {
+    #[error("Couldn't access session state file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid session state file: {0}")]
+    Format(#[from] serde_json::Error),
+
+    #[error("Couldn't export or import the transport cookies: {0}")]
+    Transport(#[source] TransportError),
+
+    #[error("The session transport doesn't support saving its cookies")]
+    Unsupported,
+}
+
+/// Errors returned when loading a [`DataModel`](crate::DataModel).
+#[derive(thiserror::Error, Debug)]
+pub enum DataModelError {
+    #[error("Couldn't read data model: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid data model: {0}")]
+    Format(#[from] serde_json::Error),
+}
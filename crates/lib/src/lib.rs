@@ -1,5 +1,32 @@
+mod availability;
+mod builder;
+mod cache;
+mod classify;
+mod data_model;
+mod error;
 mod model;
+mod office_info;
+mod persist;
+mod pool;
+mod rate_limit;
+mod retry;
 mod session;
+mod slots;
+#[cfg(test)]
+mod testing;
+mod transport;
 
+pub use availability::*;
+pub use builder::*;
+pub use cache::*;
+pub use classify::UnavailableReason;
+pub use data_model::*;
+pub use error::*;
 pub use model::*;
+pub use office_info::*;
+pub use pool::*;
+pub use rate_limit::*;
+pub use retry::*;
 pub use session::*;
+pub use slots::*;
+pub use transport::*;
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::OfficeExtendedInfo;
+
 /// The unique numeric ID of an office.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OfficeId(pub u32);
@@ -11,21 +13,70 @@ pub struct ProcedureId(pub u32);
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ProcedureOfficeId(pub u32);
 
-#[derive(Deserialize, Serialize, Debug)]
+/// The location of an office, in degrees.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    /// Mean radius of the Earth, in kilometers.
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+
+    pub const fn new(latitude: f64, longitude: f64) -> Self {
+        Coordinates {
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Returns the great-circle distance to the given coordinates, in
+    /// kilometers.
+    pub fn distance_km(&self, other: &Coordinates) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * Self::EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DataGenModel {
     pub offices: Vec<DataGenOffice>,
     pub procedures: Vec<DataGenProcedure>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DataGenOffice {
     pub name: String,
     pub group: String,
     pub id: OfficeId,
     pub procedures: Vec<DataGenOfficeProcedure>,
+    // The following fields default to empty values, so models generated
+    // before they were added can still be read.
+    #[serde(default)]
+    pub coordinates: Option<Coordinates>,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub district_code: String,
+    #[serde(default)]
+    pub district_name: String,
+    #[serde(default)]
+    pub office_code: Option<String>,
+    /// URL of the office information page.
+    #[serde(default)]
+    pub url: String,
+    /// Only downloaded on demand, since it requires fetching an additional
+    /// page per office.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extended_info: Option<OfficeExtendedInfo>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DataGenOfficeProcedure {
     pub procedure_name: String,
     pub procedure_category: String,
@@ -33,7 +84,7 @@ pub struct DataGenOfficeProcedure {
     pub procedure_id: ProcedureId,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DataGenProcedure {
     pub procedure_category: String,
     pub procedure_name: String,
@@ -46,6 +97,13 @@ pub struct StaticOffice {
     pub group: &'static str,
     pub id: OfficeId,
     pub procedures: &'static [StaticOfficeProcedure],
+    pub coordinates: Option<Coordinates>,
+    pub address: &'static str,
+    pub district_code: &'static str,
+    pub district_name: &'static str,
+    pub office_code: Option<&'static str>,
+    /// URL of the office information page.
+    pub url: &'static str,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,428 @@
+use chrono::{NaiveTime, Weekday};
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::session::ExpectedResponse;
+use crate::{AppointmentSession, Error, OfficeId, Result, TimeWindow, TransportRequest};
+
+/// Details of an office read from its information page on madrid.es, the one
+/// linked by [`NetOfficeModel::url`](crate::NetOfficeModel::url).
+///
+/// Every field is optional, since not every page includes all of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OfficeExtendedInfo {
+    /// URL of the information page.
+    pub url: String,
+    /// The opening hours of each weekday the office opens, sorted from Monday
+    /// to Sunday.
+    pub opening_hours: Vec<DayOpeningHours>,
+    /// The opening hours as written in the page, for the cases the parsed
+    /// ones miss something.
+    pub opening_hours_text: Option<String>,
+    pub phone: Option<String>,
+    pub accessibility: Vec<String>,
+    /// Public transport lines that stop near the office, one line per kind of
+    /// transport, as in "Metro: Línea 1".
+    pub transport: Vec<String>,
+}
+
+/// The opening hours of an office on a weekday.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DayOpeningHours {
+    pub weekday: Weekday,
+    pub hours: Vec<TimeWindow>,
+}
+
+lazy_static! {
+    static ref SELECTOR_SECTION_HEADINGS: Selector = Selector::parse("h2, h3, h4, dt").unwrap();
+    static ref SELECTOR_TEXT_BLOCKS: Selector = Selector::parse("p, li, dd").unwrap();
+    static ref RE_WEEKDAYS: Regex = Regex::new(
+        r"(lunes|martes|miercoles|jueves|viernes|sabado|domingo)(?:\s*(?:a|al|-)\s*(lunes|martes|miercoles|jueves|viernes|sabado|domingo))?"
+    )
+    .unwrap();
+    static ref RE_TIME_RANGE: Regex = Regex::new(
+        r"(\d{1,2})(?:[:.](\d{2}))?\s*(?:h\.?|horas)?\s*(?:a|-|–|hasta)\s*(?:las\s*)?(\d{1,2})(?:[:.](\d{2}))?"
+    )
+    .unwrap();
+    static ref RE_PHONE: Regex = Regex::new(r"(?:\+34\s*)?\d{3}(?:[\s.]?\d){6}").unwrap();
+}
+
+/// The kind of information given by a section of the page, guessed from its
+/// heading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Hours,
+    Phone,
+    Accessibility,
+    Transport,
+}
+
+impl Section {
+    fn from_heading(heading: &str) -> Option<Self> {
+        let heading = normalize(heading);
+        if heading.contains("horario") {
+            Some(Section::Hours)
+        } else if heading.contains("telefono") || heading.contains("contacto") {
+            Some(Section::Phone)
+        } else if heading.contains("accesib") {
+            Some(Section::Accessibility)
+        } else if heading.contains("transporte") || heading.contains("como llegar") {
+            Some(Section::Transport)
+        } else {
+            None
+        }
+    }
+}
+
+impl AppointmentSession {
+    /// Fetches and parses the information page of an office, as linked by
+    /// [`NetOfficeModel::url`](crate::NetOfficeModel::url).
+    pub async fn get_office_extended_info(&self, url: &str) -> Result<OfficeExtendedInfo> {
+        let url = self
+            .endpoints()
+            .base
+            .join(url)
+            .map_err(|err| Error::Parse {
+                url: url.to_string(),
+                reason: format!("Invalid office information URL: {}", err),
+                body: String::new(),
+            })?;
+
+        let body = self
+            .send_request(
+                "get_office_extended_info",
+                TransportRequest::get(url.clone()),
+                ExpectedResponse::Any,
+            )
+            .await?;
+        read_office_extended_info(&url, &body)
+    }
+
+    /// Same as [`AppointmentSession::get_office_extended_info`], but finding
+    /// the information page from the office details. Returns `None` if the
+    /// office doesn't exist or has no information page.
+    pub async fn get_office_extended_info_by_id(
+        &self,
+        office_id: OfficeId,
+    ) -> Result<Option<OfficeExtendedInfo>> {
+        let Some(office) = self.get_office_details(office_id).await? else {
+            return Ok(None);
+        };
+        if office.url.trim().is_empty() {
+            return Ok(None);
+        }
+
+        self.get_office_extended_info(&office.url).await.map(Some)
+    }
+}
+
+fn read_office_extended_info(url: &Url, body: &str) -> Result<OfficeExtendedInfo> {
+    let html = Html::parse_document(body);
+    let mut info = OfficeExtendedInfo {
+        url: url.to_string(),
+        ..Default::default()
+    };
+
+    let mut found = false;
+    for heading in html.select(&SELECTOR_SECTION_HEADINGS) {
+        let Some(section) = Section::from_heading(&element_text(heading)) else {
+            continue;
+        };
+        let lines = section_lines(heading);
+        if lines.is_empty() {
+            continue;
+        }
+        found = true;
+
+        match section {
+            Section::Hours if info.opening_hours_text.is_none() => {
+                let text = lines.join("\n");
+                info.opening_hours = parse_opening_hours(&text);
+                info.opening_hours_text = Some(text);
+            }
+            Section::Phone if info.phone.is_none() => {
+                // The pages usually mention the general 010 line of the city
+                // as well, which is only reported if there is no other one.
+                info.phone = lines
+                    .iter()
+                    .find_map(|line| RE_PHONE.find(line))
+                    .map(|phone| phone.as_str().to_string())
+                    .or_else(|| {
+                        lines
+                            .iter()
+                            .any(|line| line.contains("010"))
+                            .then(|| "010".to_string())
+                    });
+            }
+            Section::Accessibility if info.accessibility.is_empty() => info.accessibility = lines,
+            Section::Transport if info.transport.is_empty() => info.transport = lines,
+            _ => {}
+        }
+    }
+
+    if !found {
+        return Err(Error::unexpected_page(
+            url,
+            "No office information found in page",
+            body,
+        ));
+    }
+    Ok(info)
+}
+
+/// Returns the text of the elements following a heading, up to the next
+/// heading, one line per paragraph or list item.
+fn section_lines(heading: ElementRef) -> Vec<String> {
+    let mut lines = Vec::new();
+    for sibling in heading.next_siblings().filter_map(ElementRef::wrap) {
+        if SELECTOR_SECTION_HEADINGS.matches(&sibling) {
+            break;
+        }
+
+        if SELECTOR_TEXT_BLOCKS.matches(&sibling) {
+            lines.push(element_text(sibling));
+            continue;
+        }
+
+        let blocks: Vec<_> = sibling
+            .select(&SELECTOR_TEXT_BLOCKS)
+            .map(element_text)
+            .collect();
+        if blocks.is_empty() {
+            lines.push(element_text(sibling));
+        } else {
+            lines.extend(blocks);
+        }
+    }
+
+    lines.retain(|line| !line.is_empty());
+    lines
+}
+
+fn element_text(element: ElementRef) -> String {
+    element
+        .text()
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lowercases the text and removes the accents of the vowels, so it can be
+/// matched against the weekday names regardless of how they are written.
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' => 'a',
+            'é' => 'e',
+            'í' => 'i',
+            'ó' => 'o',
+            'ú' | 'ü' => 'u',
+            c => c,
+        })
+        .collect()
+}
+
+fn parse_weekday(name: &str) -> Weekday {
+    match name {
+        "lunes" => Weekday::Mon,
+        "martes" => Weekday::Tue,
+        "miercoles" => Weekday::Wed,
+        "jueves" => Weekday::Thu,
+        "viernes" => Weekday::Fri,
+        "sabado" => Weekday::Sat,
+        _ => Weekday::Sun,
+    }
+}
+
+/// Parses opening hours written as in "Lunes a viernes de 9:00 a 14:00 horas;
+/// sábados de 9 a 13 h". Each sentence is expected to name the weekdays
+/// before their hours.
+fn parse_opening_hours(text: &str) -> Vec<DayOpeningHours> {
+    let mut days: Vec<DayOpeningHours> = Vec::new();
+    let text = normalize(text);
+
+    for clause in text.split(['\n', ';']).flat_map(|line| line.split(". ")) {
+        let mut weekdays = Vec::new();
+        for caps in RE_WEEKDAYS.captures_iter(clause) {
+            let first = parse_weekday(&caps[1]);
+            let last = caps
+                .get(2)
+                .map_or(first, |last| parse_weekday(last.as_str()));
+            let mut day = first;
+            loop {
+                weekdays.push(day);
+                if day == last || weekdays.len() >= 7 {
+                    break;
+                }
+                day = day.succ();
+            }
+        }
+        if weekdays.is_empty() && (clause.contains("todos los dias") || clause.contains("diario")) {
+            weekdays.extend([
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+                Weekday::Sat,
+                Weekday::Sun,
+            ]);
+        }
+
+        let hours: Vec<_> = RE_TIME_RANGE
+            .captures_iter(clause)
+            .filter_map(|caps| {
+                let time = |hour: &str, minute: Option<regex::Match>| {
+                    NaiveTime::from_hms_opt(
+                        hour.parse().ok()?,
+                        minute.map_or(Some(0), |minute| minute.as_str().parse().ok())?,
+                        0,
+                    )
+                };
+                let window =
+                    TimeWindow::new(time(&caps[1], caps.get(2))?, time(&caps[3], caps.get(4))?);
+                (window.start < window.end).then_some(window)
+            })
+            .collect();
+
+        if hours.is_empty() {
+            continue;
+        }
+
+        for weekday in weekdays {
+            match days.iter_mut().find(|day| day.weekday == weekday) {
+                Some(day) => day.hours.extend(hours.iter().copied()),
+                None => days.push(DayOpeningHours {
+                    weekday,
+                    hours: hours.clone(),
+                }),
+            }
+        }
+    }
+
+    days.sort_by_key(|day| day.weekday.num_days_from_monday());
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Weekday::*;
+
+    use super::*;
+    use crate::testing::FakeTransport;
+
+    const OFFICE_INFO_PAGE: &str = include_str!("../tests/fixtures/office_info.html");
+    const OFFICE_INFO_URL: &str =
+        "https://www.madrid.es/portales/munimadrid/es/Inicio/linea-madrid-centro";
+
+    fn window(start: (u32, u32), end: (u32, u32)) -> TimeWindow {
+        TimeWindow::new(
+            NaiveTime::from_hms_opt(start.0, start.1, 0).unwrap(),
+            NaiveTime::from_hms_opt(end.0, end.1, 0).unwrap(),
+        )
+    }
+
+    fn days(days: &[Weekday], hours: &[TimeWindow]) -> Vec<DayOpeningHours> {
+        days.iter()
+            .map(|&weekday| DayOpeningHours {
+                weekday,
+                hours: hours.to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn opening_hours_with_weekday_ranges() {
+        let mut expected = days(&[Mon, Tue, Wed, Thu, Fri], &[window((9, 0), (14, 0))]);
+        expected.extend(days(&[Sat], &[window((9, 0), (13, 0))]));
+
+        assert_eq!(
+            parse_opening_hours("Lunes a viernes de 9:00 a 14:00; sábados de 9 a 13 h"),
+            expected
+        );
+    }
+
+    #[test]
+    fn opening_hours_with_several_windows_per_day() {
+        assert_eq!(
+            parse_opening_hours("Lunes y miércoles, de 8.30 a 14 horas y de 16:00 a 18:30."),
+            days(
+                &[Mon, Wed],
+                &[window((8, 30), (14, 0)), window((16, 0), (18, 30))]
+            )
+        );
+    }
+
+    #[test]
+    fn opening_hours_every_day() {
+        assert_eq!(
+            parse_opening_hours("Todos los días de 10 a 20 h"),
+            days(
+                &[Mon, Tue, Wed, Thu, Fri, Sat, Sun],
+                &[window((10, 0), (20, 0))]
+            )
+        );
+    }
+
+    #[test]
+    fn opening_hours_without_hours_are_skipped() {
+        assert_eq!(parse_opening_hours("Domingos y festivos: cerrado"), []);
+    }
+
+    #[test]
+    fn office_info_page_is_read_by_section() {
+        let url = Url::parse(OFFICE_INFO_URL).unwrap();
+        let info = read_office_extended_info(&url, OFFICE_INFO_PAGE).unwrap();
+
+        let mut expected_hours = days(&[Mon, Tue, Wed, Thu, Fri], &[window((9, 0), (14, 0))]);
+        expected_hours.extend(days(&[Sat], &[window((9, 0), (13, 0))]));
+        assert_eq!(info.opening_hours, expected_hours);
+        assert_eq!(
+            info.opening_hours_text.as_deref(),
+            Some(
+                "Lunes a viernes de 9:00 a 14:00; sábados de 9 a 13 h\n\
+                 Julio y agosto: cerrado los sábados."
+            )
+        );
+        assert_eq!(info.phone.as_deref(), Some("915 298 210"));
+        assert_eq!(
+            info.accessibility,
+            [
+                "Acceso adaptado para personas con movilidad reducida.",
+                "Ascensor fuera de servicio temporalmente.",
+            ]
+        );
+        assert_eq!(
+            info.transport,
+            [
+                "Metro: Sol (L1, L2, L3)",
+                "Autobuses: 3, 148",
+                "Cercanías: Sol"
+            ]
+        );
+    }
+
+    #[test]
+    fn page_without_office_information_is_unexpected() {
+        let url = Url::parse(OFFICE_INFO_URL).unwrap();
+        let err =
+            read_office_extended_info(&url, "<html><body><p>Hola</p></body></html>").unwrap_err();
+        assert!(matches!(err, Error::UnexpectedPage { .. }), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn office_info_pages_are_not_classified() {
+        let transport = FakeTransport::with_bodies(|_| OFFICE_INFO_PAGE.to_string());
+
+        let info = transport
+            .session()
+            .get_office_extended_info(OFFICE_INFO_URL)
+            .await
+            .unwrap();
+        assert_eq!(info.phone.as_deref(), Some("915 298 210"));
+    }
+}
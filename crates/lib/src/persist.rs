@@ -0,0 +1,202 @@
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{PersistError, Transport};
+
+/// Name of the directory the files of the library are kept in, inside the
+/// user cache and data directories.
+pub(crate) const APP_DIR_NAME: &str = "madrid-cita-previa";
+
+/// The state of a session as saved to a file.
+#[derive(Serialize, Deserialize)]
+struct PersistedSession {
+    saved_at: SystemTime,
+    /// Base URL of the session, so the state of a session against a different
+    /// server is never restored.
+    base_url: String,
+    initialized: bool,
+    /// The cookie jar of the transport, as serialized by
+    /// [`Transport::export_cookies`].
+    cookies: String,
+}
+
+/// A session state read back from a file.
+pub(crate) struct RestoredSession {
+    pub initialized: bool,
+    pub age: Duration,
+}
+
+/// Saves the cookie jar of the transport to the given file, along with whether
+/// the session is initialized.
+pub(crate) fn save_session(
+    transport: &dyn Transport,
+    base_url: &Url,
+    path: &Path,
+    initialized: bool,
+) -> Result<(), PersistError> {
+    let cookies = transport
+        .export_cookies()
+        .map_err(PersistError::Transport)?
+        .ok_or(PersistError::Unsupported)?;
+
+    let data = serde_json::to_vec(&PersistedSession {
+        saved_at: SystemTime::now(),
+        base_url: base_url.to_string(),
+        initialized,
+        cookies,
+    })?;
+
+    // The cookies give access to the session, so only the user may read them.
+    write_private_file_atomically(path, &data)?;
+    Ok(())
+}
+
+/// Restores the cookie jar of the transport from the given file. Returns
+/// `None` if the file doesn't exist or belongs to a session against another
+/// base URL.
+pub(crate) fn restore_session(
+    transport: &dyn Transport,
+    base_url: &Url,
+    path: &Path,
+) -> Result<Option<RestoredSession>, PersistError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let persisted: PersistedSession = serde_json::from_slice(&data)?;
+    if persisted.base_url != base_url.as_str() {
+        return Ok(None);
+    }
+
+    if !transport
+        .import_cookies(&persisted.cookies)
+        .map_err(PersistError::Transport)?
+    {
+        return Err(PersistError::Unsupported);
+    }
+
+    Ok(Some(RestoredSession {
+        initialized: persisted.initialized,
+        age: persisted.saved_at.elapsed().unwrap_or_default(),
+    }))
+}
+
+/// Writes the given data to a file, creating its directory if needed.
+///
+/// The data is written to a temporary file in the same directory first, and
+/// then renamed to the given path, so readers never see a file partially
+/// written. The temporary file is unique to each call, so concurrent writers,
+/// even from different processes, never mix their data.
+pub(crate) fn write_file_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomically(path, data, false)
+}
+
+/// Same as [`write_file_atomically`], but on Unix the file is only readable
+/// and writable by its owner.
+pub(crate) fn write_private_file_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomically(path, data, true)
+}
+
+fn write_atomically(path: &Path, data: &[u8], private: bool) -> io::Result<()> {
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    let Some(file_name) = path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Not a file path: {}", path.display()),
+        ));
+    };
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = match dir {
+        Some(dir) => dir.join(tmp_name),
+        None => tmp_name.into(),
+    };
+
+    let result = write_new_file(&tmp_path, data, private).and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn write_new_file(path: &Path, data: &[u8], private: bool) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+
+    options.open(path)?.write_all(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::testing::{FakeTransport, TestDir};
+
+    #[test]
+    fn concurrent_writes_never_mix() {
+        let dir = TestDir::new("atomic-write");
+        let path = dir.join("state.json");
+
+        let payloads: Vec<Vec<u8>> = (0..8u8).map(|i| vec![b'a' + i; 256 * 1024]).collect();
+        thread::scope(|scope| {
+            for payload in &payloads {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..4 {
+                        write_file_atomically(path, payload).unwrap();
+                    }
+                });
+            }
+        });
+
+        let written = fs::read(&path).unwrap();
+        assert!(payloads.contains(&written));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn session_file_is_only_readable_by_its_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new("session-file-mode");
+        let path = dir.join("session.json");
+        let transport = FakeTransport::with_bodies(|_| String::new());
+        let base_url = Url::parse(crate::DEFAULT_BASE_URL).unwrap();
+
+        save_session(&transport, &base_url, &path, true).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,471 @@
+use std::{
+    ops::Deref,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use futures_util::future;
+use log::{info, warn};
+use tokio::time::Instant;
+
+use crate::{AppointmentSession, BuildError, Error, Result, UnavailableReason};
+
+/// How a [`SessionPool`] picks the session handed out next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PoolStrategy {
+    /// Hands out the sessions in turns.
+    #[default]
+    RoundRobin,
+    /// Hands out the session that has gone unused for the longest time.
+    LeastRecentlyUsed,
+}
+
+/// Options for building a [`SessionPool`].
+#[derive(Debug, Clone)]
+pub struct PoolOptions {
+    /// Number of sessions in the pool.
+    pub size: usize,
+    pub strategy: PoolStrategy,
+    /// Number of failures in a row after which a session is replaced by a new
+    /// one.
+    pub max_consecutive_failures: u32,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            size: 4,
+            strategy: PoolStrategy::default(),
+            max_consecutive_failures: 3,
+        }
+    }
+}
+
+/// The health of a session of a [`SessionPool`].
+#[derive(Debug, Clone)]
+pub struct SessionHealth {
+    /// Number of failures in a row of the session, not counting the ones
+    /// caused by the contents of a page, which don't get the session
+    /// replaced.
+    pub consecutive_failures: u32,
+    /// Number of operations of the session that succeeded.
+    pub successes: u64,
+    /// Number of operations of the session that failed.
+    pub failures: u64,
+    /// When the session was last handed out, if ever.
+    pub last_used: Option<Instant>,
+}
+
+impl SessionHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0
+    }
+}
+
+/// The health of a [`SessionPool`] and each of its sessions.
+#[derive(Debug, Clone)]
+pub struct PoolHealth {
+    pub sessions: Vec<SessionHealth>,
+    /// Number of sessions replaced so far because they kept failing.
+    pub evictions: u64,
+}
+
+impl PoolHealth {
+    /// Returns the number of sessions whose last operation succeeded, or that
+    /// haven't been used yet.
+    pub fn healthy(&self) -> usize {
+        self.sessions
+            .iter()
+            .filter(|session| session.is_healthy())
+            .count()
+    }
+}
+
+type SessionFactory = dyn Fn() -> std::result::Result<AppointmentSession, BuildError> + Send + Sync;
+
+struct PoolSlot {
+    session: Arc<AppointmentSession>,
+    /// Incremented every time the session of the slot is replaced, so the
+    /// outcomes reported for the old session are ignored.
+    generation: u64,
+    health: SessionHealth,
+}
+
+/// A pool of independent [`AppointmentSession`]s, for spreading many queries
+/// across several server-side sessions.
+///
+/// Sessions are handed out according to the pool [`PoolStrategy`]. The
+/// outcome of the operations performed with them is expected to be reported
+/// back, either through [`SessionPool::run`] or [`PooledSession::report`], so
+/// the sessions that keep failing are replaced by new ones.
+///
+/// Each session enforces its own rate limit, so a pool of sessions sends as
+/// many requests as all of them together. For limiting the pool as a whole,
+/// the factory has to build every session with the same limiter, through
+/// [`AppointmentSessionBuilder::shared_rate_limiter`](crate::AppointmentSessionBuilder::shared_rate_limiter).
+pub struct SessionPool {
+    slots: Vec<Mutex<PoolSlot>>,
+    factory: Box<SessionFactory>,
+    options: PoolOptions,
+    next: AtomicUsize,
+    /// Serializes the selection of the least recently used session.
+    selection: Mutex<()>,
+    evictions: AtomicU64,
+}
+
+/// A session handed out by a [`SessionPool`].
+pub struct PooledSession<'a> {
+    pool: &'a SessionPool,
+    index: usize,
+    generation: u64,
+    session: Arc<AppointmentSession>,
+}
+
+impl Deref for PooledSession<'_> {
+    type Target = AppointmentSession;
+
+    fn deref(&self) -> &Self::Target {
+        &self.session
+    }
+}
+
+impl PooledSession<'_> {
+    /// Reports the outcome of an operation performed with the session to the
+    /// pool it belongs to.
+    pub fn report<T>(&self, result: &Result<T>) {
+        self.pool
+            .report(self.index, self.generation, result.as_ref().err());
+    }
+}
+
+impl SessionPool {
+    /// Creates a pool with sessions created by the given factory, which is
+    /// also used for replacing the sessions that keep failing.
+    pub fn new(
+        options: PoolOptions,
+        factory: impl Fn() -> std::result::Result<AppointmentSession, BuildError>
+        + Send
+        + Sync
+        + 'static,
+    ) -> std::result::Result<Self, BuildError> {
+        let slots = (0..options.size.max(1))
+            .map(|_| {
+                Ok(Mutex::new(PoolSlot {
+                    session: Arc::new(factory()?),
+                    generation: 0,
+                    health: SessionHealth {
+                        consecutive_failures: 0,
+                        successes: 0,
+                        failures: 0,
+                        last_used: None,
+                    },
+                }))
+            })
+            .collect::<std::result::Result<_, BuildError>>()?;
+
+        Ok(SessionPool {
+            slots,
+            factory: Box::new(factory),
+            options,
+            next: AtomicUsize::new(0),
+            selection: Mutex::new(()),
+            evictions: AtomicU64::new(0),
+        })
+    }
+
+    /// Creates a pool of sessions with the default configuration.
+    pub fn with_default_sessions(options: PoolOptions) -> std::result::Result<Self, BuildError> {
+        Self::new(options, || AppointmentSession::builder().build())
+    }
+
+    pub fn size(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Initializes all the sessions of the pool at once, and returns the first
+    /// failure, if any.
+    pub async fn ensure_init(&self) -> Result<()> {
+        let sessions: Vec<_> = self
+            .slots
+            .iter()
+            .map(|slot| slot.lock().unwrap().session.clone())
+            .collect();
+        future::try_join_all(sessions.iter().map(|session| session.ensure_init())).await?;
+        Ok(())
+    }
+
+    /// Hands out the next session according to the pool strategy.
+    pub fn get(&self) -> PooledSession<'_> {
+        // Held until the chosen session is marked as used, so concurrent
+        // calls never pick the same least recently used session.
+        let _selection = match self.options.strategy {
+            PoolStrategy::RoundRobin => None,
+            PoolStrategy::LeastRecentlyUsed => Some(self.selection.lock().unwrap()),
+        };
+        let index = match self.options.strategy {
+            PoolStrategy::RoundRobin => {
+                self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len()
+            }
+            PoolStrategy::LeastRecentlyUsed => self
+                .slots
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, slot)| slot.lock().unwrap().health.last_used)
+                .map(|(index, _)| index)
+                .unwrap(),
+        };
+
+        let mut slot = self.slots[index].lock().unwrap();
+        slot.health.last_used = Some(Instant::now());
+        PooledSession {
+            pool: self,
+            index,
+            generation: slot.generation,
+            session: slot.session.clone(),
+        }
+    }
+
+    /// Runs the given operation with the next session of the pool, and
+    /// reports its outcome.
+    pub async fn run<T, F, Fut>(&self, operation: F) -> Result<T>
+    where
+        F: FnOnce(Arc<AppointmentSession>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let pooled = self.get();
+        let result = operation(pooled.session.clone()).await;
+        pooled.report(&result);
+        result
+    }
+
+    pub fn health(&self) -> PoolHealth {
+        PoolHealth {
+            sessions: self
+                .slots
+                .iter()
+                .map(|slot| slot.lock().unwrap().health.clone())
+                .collect(),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn report(&self, index: usize, generation: u64, error: Option<&Error>) {
+        let (error, consecutive_failures) = {
+            let mut slot = self.slots[index].lock().unwrap();
+            if slot.generation != generation {
+                return;
+            }
+
+            let Some(error) = error else {
+                slot.health.consecutive_failures = 0;
+                slot.health.successes += 1;
+                return;
+            };
+
+            // Errors caused by the contents of a page being unexpected, or by
+            // the service being down for maintenance, are not the fault of
+            // the session, and would happen with any other one, so they are
+            // counted but don't get the session replaced.
+            slot.health.failures += 1;
+            if matches!(
+                error,
+                Error::UnexpectedPage { .. }
+                    | Error::Parse { .. }
+                    | Error::ServiceUnavailable {
+                        reason: UnavailableReason::Maintenance,
+                        ..
+                    }
+            ) {
+                return;
+            }
+
+            slot.health.consecutive_failures += 1;
+            if slot.health.consecutive_failures < self.options.max_consecutive_failures {
+                return;
+            }
+            (error, slot.health.consecutive_failures)
+        };
+
+        // Built without holding the lock of the slot, so the rest of the
+        // reports and the health of the pool are not blocked meanwhile.
+        let session = match (self.factory)() {
+            Ok(session) => session,
+            Err(err) => {
+                warn!("Couldn't replace failing pool session {}: {}", index, err);
+                return;
+            }
+        };
+
+        let mut slot = self.slots[index].lock().unwrap();
+        // The session may have been replaced meanwhile by another report.
+        if slot.generation != generation {
+            return;
+        }
+        info!(
+            "Replacing pool session {} after {} failures in a row, last one: {}",
+            index, consecutive_failures, error
+        );
+        slot.session = Arc::new(session);
+        slot.generation += 1;
+        slot.health.consecutive_failures = 0;
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        ExpectedResponse, RateLimit, RateLimiter, TransportRequest, testing::FakeTransport,
+    };
+
+    fn pool(max_consecutive_failures: u32) -> SessionPool {
+        let options = PoolOptions {
+            size: 1,
+            max_consecutive_failures,
+            ..Default::default()
+        };
+        SessionPool::new(options, || {
+            Ok(FakeTransport::with_bodies(|_| String::new()).session())
+        })
+        .unwrap()
+    }
+
+    fn parse_error() -> Result<()> {
+        Err(Error::Parse {
+            url: "https://servpub.madrid.es/".to_string(),
+            reason: "Invalid page".to_string(),
+            body: String::new(),
+        })
+    }
+
+    fn transport_error() -> Result<()> {
+        Err(Error::Transport {
+            url: "https://servpub.madrid.es/".to_string(),
+            source: "Connection reset".into(),
+        })
+    }
+
+    fn pool_with_strategy(strategy: PoolStrategy) -> SessionPool {
+        let options = PoolOptions {
+            size: 3,
+            strategy,
+            ..Default::default()
+        };
+        SessionPool::new(options, || {
+            Ok(FakeTransport::with_bodies(|_| String::new()).session())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn round_robin_hands_out_sessions_in_turns() {
+        let pool = pool_with_strategy(PoolStrategy::RoundRobin);
+
+        let indexes: Vec<_> = (0..5).map(|_| pool.get().index).collect();
+        assert_eq!(indexes, [0, 1, 2, 0, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn least_recently_used_hands_out_the_longest_unused_session() {
+        let pool = pool_with_strategy(PoolStrategy::LeastRecentlyUsed);
+
+        let mut indexes = Vec::new();
+        for _ in 0..3 {
+            indexes.push(pool.get().index);
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+        assert_eq!(indexes, [0, 1, 2]);
+
+        assert_eq!(pool.get().index, 0);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(pool.get().index, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_rate_limiter_limits_the_whole_pool() {
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimit::new(1.0, 1)));
+        let options = PoolOptions {
+            size: 3,
+            ..Default::default()
+        };
+        let pool = {
+            let rate_limiter = rate_limiter.clone();
+            SessionPool::new(options, move || {
+                AppointmentSession::builder()
+                    .transport(FakeTransport::with_bodies(|_| String::new()))
+                    .shared_rate_limiter(rate_limiter.clone())
+                    .build()
+            })
+            .unwrap()
+        };
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            let session = pool.get();
+            let url = session.endpoints().base.clone();
+            session
+                .send_request("test", TransportRequest::get(url), ExpectedResponse::Any)
+                .await
+                .unwrap();
+        }
+        // The second and third requests wait for the budget of the first
+        // one, despite being sent by other sessions.
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn page_errors_count_as_failures_without_eviction() {
+        let pool = pool(2);
+        pool.get().report(&transport_error());
+        pool.get().report(&parse_error());
+        pool.get().report(&parse_error());
+
+        let health = pool.health();
+        let session = &health.sessions[0];
+        assert_eq!(session.successes, 0);
+        assert_eq!(session.failures, 3);
+        assert_eq!(session.consecutive_failures, 1);
+        assert_eq!(health.evictions, 0);
+    }
+
+    fn maintenance_error() -> Result<()> {
+        Err(Error::ServiceUnavailable {
+            url: "https://servpub.madrid.es/".to_string(),
+            reason: UnavailableReason::Maintenance,
+            retry_after: None,
+            body: String::new(),
+        })
+    }
+
+    #[test]
+    fn maintenance_errors_count_as_failures_without_eviction() {
+        let pool = pool(2);
+        for _ in 0..3 {
+            pool.get().report(&maintenance_error());
+        }
+
+        let health = pool.health();
+        assert_eq!(health.sessions[0].failures, 3);
+        assert_eq!(health.sessions[0].consecutive_failures, 0);
+        assert_eq!(health.evictions, 0);
+    }
+
+    #[test]
+    fn failing_session_is_replaced() {
+        let pool = pool(2);
+        pool.get().report(&transport_error());
+        pool.get().report(&Ok(()));
+        pool.get().report(&transport_error());
+        pool.get().report(&transport_error());
+
+        let health = pool.health();
+        assert_eq!(health.evictions, 1);
+        assert_eq!(health.sessions[0].consecutive_failures, 0);
+        assert_eq!(health.sessions[0].successes, 1);
+    }
+}
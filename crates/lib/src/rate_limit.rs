@@ -0,0 +1,141 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+/// Budget of requests a session is allowed to send to the server.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Sustained number of requests per second.
+    pub requests_per_second: f64,
+    /// Maximum number of requests that can be sent at once after the session
+    /// has been idle for a while.
+    pub burst: u32,
+}
+
+/// Lowest sustained rate accepted, of one request per day. Lower rates would
+/// make the time to wait for a request too long to be represented.
+pub const MIN_REQUESTS_PER_SECOND: f64 = 1.0 / 86_400.0;
+
+impl RateLimit {
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        RateLimit {
+            requests_per_second,
+            burst,
+        }
+    }
+
+    /// Returns whether the rate is finite and at least
+    /// [`MIN_REQUESTS_PER_SECOND`], as required by
+    /// [`AppointmentSessionBuilder::build`](crate::AppointmentSessionBuilder::build).
+    pub fn is_valid(&self) -> bool {
+        self.requests_per_second.is_finite() && self.requests_per_second >= MIN_REQUESTS_PER_SECOND
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket enforcing a [`RateLimit`] across all the requests of a session,
+/// regardless of the task they are sent from.
+///
+/// A limiter may also be shared by several sessions, through
+/// [`AppointmentSessionBuilder::shared_rate_limiter`](crate::AppointmentSessionBuilder::shared_rate_limiter),
+/// so the limit applies to all of them together, as for the sessions of a
+/// [`SessionPool`](crate::SessionPool).
+pub struct RateLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.burst.max(1) as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Waits until a request can be sent without exceeding the limit.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.limit.requests_per_second)
+                    .min(self.limit.burst.max(1) as f64);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.limit.requests_per_second)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_is_served_at_once_and_then_spaced() {
+        let limiter = RateLimiter::new(RateLimit::new(4.0, 3));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        for n in 1..=3 {
+            limiter.acquire().await;
+            let expected = Duration::from_millis(250 * n);
+            let elapsed = start.elapsed();
+            assert!(
+                elapsed >= expected && elapsed < expected + Duration::from_millis(5),
+                "request {} sent after {:?}",
+                n,
+                elapsed
+            );
+        }
+    }
+
+    #[test]
+    fn rates_too_low_or_not_finite_are_invalid() {
+        for requests_per_second in [0.0, -1.0, 1e-20, f64::NAN, f64::INFINITY] {
+            assert!(!RateLimit::new(requests_per_second, 1).is_valid());
+        }
+        assert!(RateLimit::new(MIN_REQUESTS_PER_SECOND, 1).is_valid());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_time_refills_up_to_burst() {
+        let limiter = RateLimiter::new(RateLimit::new(1.0, 2));
+        limiter.acquire().await;
+        limiter.acquire().await;
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let start = Instant::now();
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::Error;
+
+/// Policy for retrying the requests that fail because of transient errors,
+/// such as connection resets or 5xx responses from the server.
+///
+/// All the requests performed by the session are queries that can be safely
+/// repeated, so the policy applies to every one of them.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of times a request is sent, including the first one.
+    pub max_attempts: u32,
+    /// Time to wait before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the time waited between two attempts.
+    pub max_backoff: Duration,
+    /// Factor the backoff is multiplied by after each retry. Must be finite
+    /// and at least 1.
+    pub multiplier: f64,
+    /// Fraction of the backoff randomly added or subtracted to it, between 0
+    /// and 1, so concurrent clients don't retry all at once.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether the multiplier of the policy is finite and at least 1,
+    /// as required by [`AppointmentSessionBuilder::build`](crate::AppointmentSessionBuilder::build).
+    pub fn is_valid(&self) -> bool {
+        self.multiplier.is_finite() && self.multiplier >= 1.0
+    }
+
+    /// Returns whether a request that failed with the given error on its
+    /// `attempt`-th try (starting from 1) should be sent again.
+    ///
+    /// Requests the server asked to wait for longer than `max_backoff` before
+    /// sending them again are not retried, leaving it up to the caller to
+    /// wait for that long.
+    pub fn should_retry(&self, error: &Error, attempt: u32) -> bool {
+        attempt < self.max_attempts
+            && error.is_retryable()
+            && error
+                .retry_after()
+                .is_none_or(|retry_after| retry_after <= self.max_backoff)
+    }
+
+    /// Returns the time to wait before sending the `retry`-th retry (starting
+    /// from 1) of a request that failed with the given error: its backoff, or
+    /// how long the server asked to wait if longer.
+    pub fn retry_delay(&self, error: &Error, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        error
+            .retry_after()
+            .map_or(backoff, |retry_after| backoff.max(retry_after))
+    }
+
+    /// Returns the time to wait before sending the `retry`-th retry (starting
+    /// from 1) of a request.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        // Capped before building the duration, since the exponential quickly
+        // overflows it. A NaN from an invalid multiplier is capped too.
+        let exp = self
+            .multiplier
+            .powi(retry.saturating_sub(1).min(i32::MAX as u32) as i32);
+        let base = (self.initial_backoff.as_secs_f64() * exp)
+            .min(self.max_backoff.as_secs_f64())
+            .max(0.0);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::rng().random_range((1.0 - jitter)..=(1.0 + jitter))
+        } else {
+            1.0
+        };
+
+        Duration::try_from_secs_f64(base * factor).unwrap_or(self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_max_backoff() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+        assert_eq!(policy.backoff(6), Duration::from_secs(10));
+    }
+
+    fn throttled(retry_after: Option<Duration>) -> Error {
+        Error::HttpStatus {
+            url: "https://servpub.madrid.es/".to_string(),
+            status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+            retry_after,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn retry_after_hint_extends_the_backoff() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..Default::default()
+        };
+
+        let error = throttled(Some(Duration::from_secs(5)));
+        assert!(policy.should_retry(&error, 1));
+        assert_eq!(policy.retry_delay(&error, 1), Duration::from_secs(5));
+
+        let error = throttled(Some(Duration::from_millis(100)));
+        assert_eq!(policy.retry_delay(&error, 1), Duration::from_millis(500));
+        assert_eq!(
+            policy.retry_delay(&throttled(None), 2),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn retry_after_hint_longer_than_max_backoff_is_not_retried() {
+        let policy = RetryPolicy::default();
+
+        assert!(!policy.should_retry(&throttled(Some(Duration::from_secs(60))), 1));
+        let error = Error::ServiceUnavailable {
+            url: "https://servpub.madrid.es/".to_string(),
+            reason: crate::UnavailableReason::ErrorPage,
+            retry_after: Some(Duration::from_secs(60)),
+            body: String::new(),
+        };
+        assert!(!policy.should_retry(&error, 1));
+    }
+
+    #[test]
+    fn backoff_doesnt_overflow_after_many_retries() {
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            ..Default::default()
+        };
+
+        for retry in [60, 99, 1100, u32::MAX] {
+            assert!(policy.backoff(retry) <= Duration::from_secs(12));
+        }
+    }
+
+    #[test]
+    fn backoff_doesnt_panic_on_invalid_multiplier() {
+        for multiplier in [-2.0, f64::NAN, f64::INFINITY] {
+            let policy = RetryPolicy {
+                multiplier,
+                ..Default::default()
+            };
+
+            assert!(!policy.is_valid());
+            for retry in 1..5 {
+                assert!(policy.backoff(retry) <= Duration::from_secs(12));
+            }
+        }
+    }
+}
@@ -1,44 +1,101 @@
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
-use anyhow::{Context, bail};
 use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeZone};
 use chrono::{NaiveDate, Utc};
+use chrono_tz::Tz;
 use lazy_static::lazy_static;
-use log::{debug, trace};
+use log::{debug, info, trace, warn};
 use regex::Regex;
-use reqwest::RequestBuilder;
 use reqwest::{
-    ClientBuilder, Response, Url,
-    header::{ACCEPT, CONTENT_LENGTH, HeaderMap, HeaderValue, USER_AGENT},
+    ClientBuilder, StatusCode, Url,
+    header::{ACCEPT, CONTENT_LENGTH, HeaderMap, HeaderValue},
 };
 use scraper::{Html, Selector};
-use serde::{Deserialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{Map, Value};
 use tokio::sync::Mutex;
 
-use crate::{OfficeId, ProcedureId, ProcedureOfficeId};
+use crate::classify::{classify_response, retry_after_header};
+use crate::error::truncate_body;
+use crate::persist::{restore_session, save_session};
+use crate::{
+    AppointmentDays, AppointmentSessionBuilder, DaySlots, DaySlotsQuery, Error, HourlySlots,
+    MinuteSlots, OfficeId, PersistError, ProcedureAppointments, ProcedureId, ProcedureOfficeId,
+    RateLimiter, Result, RetryPolicy, Slot, Transport, TransportRequest, TransportResponse,
+};
+
+/// Maximum number of requests sent for fetching the slots of a single day.
+const MAX_DAY_SLOTS_PAGES: usize = 8;
 
 #[derive(Default)]
 struct SessionState {
     init: bool,
+    /// Incremented every time the session is initialized, so concurrent
+    /// requests detecting the same expired session only reset it once.
+    generation: u64,
+    /// Whether the state file has already been read, so it is only restored
+    /// once.
+    restore_attempted: bool,
+}
+
+/// The kind of response expected from an endpoint, used for detecting when the
+/// server answers with something else because the session has expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExpectedResponse {
+    /// Any response is accepted.
+    Any,
+    /// An HTML page served by the requested endpoint.
+    Html,
+    /// A JSON document served by the requested endpoint.
+    Json,
+}
+
+/// URLs of all the endpoints used by the session, derived from its base URL.
+#[derive(Debug, Clone)]
+pub(crate) struct Endpoints {
+    pub(crate) base: Url,
+    pub(crate) ajax_auth: Url,
+    pub(crate) closest_appointment_office: Url,
+    pub(crate) office_appointments: Url,
+    pub(crate) appointments_by_office_landing: Url,
+    pub(crate) appointments_by_procedure_landing: Url,
+    pub(crate) day_appointment_slots: Url,
+    pub(crate) office_info: Url,
+}
+
+impl Endpoints {
+    /// Builds the endpoints from the given base URL, which must end with a
+    /// slash so the endpoints are resolved inside of it.
+    pub(crate) fn new(base: Url) -> std::result::Result<Self, url::ParseError> {
+        Ok(Endpoints {
+            ajax_auth: base.join("AjaxPantallaAcceso")?,
+            closest_appointment_office: base.join("oficinaCitaProxima.do")?,
+            office_appointments: base.join("horarioOficina.do")?,
+            appointments_by_office_landing: base.join("oficina.do")?,
+            appointments_by_procedure_landing: base.join("tramite.do")?,
+            day_appointment_slots: base.join("franjasDia.do")?,
+            office_info: base.join("dameOficina.do")?,
+            base,
+        })
+    }
 }
 
 pub struct AppointmentSession {
-    client: reqwest::Client,
+    transport: Arc<dyn Transport>,
+    endpoints: Endpoints,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    default_headers: HeaderMap,
+    state_file: Option<PathBuf>,
     state: Arc<Mutex<SessionState>>,
 }
 
 lazy_static! {
-    static ref BASE_URL: Url = Url::parse("https://servpub.madrid.es/GNSIS_WBCIUDADANO/").unwrap();
-    static ref ENDPOINT_AJAX_AUTH: Url = BASE_URL.join("AjaxPantallaAcceso").unwrap();
-    static ref ENDPOINT_CLOSEST_APPOINTMENT_OFFICE: Url =
-        BASE_URL.join("oficinaCitaProxima.do").unwrap();
-    static ref ENDPOINT_OFFICE_APPOINTMENTS: Url = BASE_URL.join("horarioOficina.do").unwrap();
-    static ref ENDPOINT_APPOINTMENTS_BY_OFFICE_LANDING: Url = BASE_URL.join("oficina.do").unwrap();
-    static ref ENDPOINT_APPOINTMENTS_BY_PROCEDURE_LANDING: Url =
-        BASE_URL.join("tramite.do").unwrap();
-    static ref ENDPOINT_DAY_APPOINTMENT_SLOTS: Url = BASE_URL.join("franjasDia.do").unwrap();
-    static ref ENDPOINT_OFFICE_INFO: Url = BASE_URL.join("dameOficina.do").unwrap();
     static ref RE_AVAILABLE_APPOINTMENTS: Regex =
         Regex::new(r#"JSON\.parse\(\s*'([^']*)'\s*\)"#).unwrap();
     static ref SELECTOR_PROCEDURES_COMBOBOX: Selector =
@@ -47,9 +104,13 @@ lazy_static! {
         Selector::parse("select[id=selectOficinas]").unwrap();
     static ref SELECTOR_OPTGROUP: Selector = Selector::parse("optgroup").unwrap();
     static ref SELECTOR_OPTION: Selector = Selector::parse("option").unwrap();
+    static ref SELECTOR_INPUT_OFFICE_ID: Selector =
+        Selector::parse("input[name=idOficina]").unwrap();
+    static ref SELECTOR_INPUT_PROCEDURE_OFFICE_ID: Selector =
+        Selector::parse("input[name=idServicio]").unwrap();
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NetOfficeProcedureModel {
     #[serde(rename = "categoria")]
     pub category: String,
@@ -61,7 +122,7 @@ pub struct NetOfficeProcedureModel {
     pub procedure_id: ProcedureId,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NetOfficeModel {
     #[serde(rename = "idOficina")]
     pub office_id: u32,
@@ -85,7 +146,7 @@ pub struct NetOfficeModel {
     pub procedures: Vec<NetOfficeProcedureModel>,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NetOfficeBasicInfoModel {
     pub name: String,
     pub group: String,
@@ -102,6 +163,13 @@ pub struct NetAppointment {
     pub year: u32,
 }
 
+struct ComboboxOption {
+    group: String,
+    name: String,
+    value: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NetProcedureModel {
     pub procedure_category: String,
     pub procedure_name: String,
@@ -129,126 +197,397 @@ pub struct NetAppointmentHourlySlots {
     slot_sets: Vec<NetAppointmentSlotSet>,
 }
 
+/// Builds the date and time of a slot from its day and its local time, as
+/// returned by the server in `HH:MM` format.
+///
+/// Times that happen twice in Madrid because of the end of daylight saving
+/// time are resolved to their first occurrence. Times that don't exist
+/// because of the start of daylight saving time are returned as `None`, so
+/// the slot can be skipped. Times not in the expected format are errors.
+fn slot_datetime(
+    day: NaiveDate,
+    raw_time: &str,
+) -> std::result::Result<Option<DateTime<Tz>>, String> {
+    let time = NaiveTime::parse_from_str(raw_time.trim(), "%H:%M")
+        .map_err(|err| format!("Invalid slot time {:?}: {}", raw_time, err))?;
+
+    Ok(chrono_tz::Europe::Madrid
+        .from_local_datetime(&NaiveDateTime::new(day, time))
+        .earliest())
+}
+
 impl AppointmentSession {
     pub fn new(cb: ClientBuilder) -> Self {
-        let mut headers = HeaderMap::new();
-        headers.append(
-            USER_AGENT,
-            HeaderValue::from_static(
-                "Mozilla/5.0 (X11; Linux x86_64; rv:141.0) Gecko/20100101 Firefox/141.0",
-            ),
-        );
-        let client = cb
-            .cookie_store(true)
-            .default_headers(headers)
-            .build()
-            .unwrap();
+        Self::builder().client_builder(cb).build().unwrap()
+    }
+
+    /// Creates a session that sends all its requests through the given
+    /// transport.
+    pub fn with_transport(transport: impl Transport + 'static) -> Self {
+        Self::builder().transport(transport).build().unwrap()
+    }
 
+    pub fn builder() -> AppointmentSessionBuilder {
+        AppointmentSessionBuilder::new()
+    }
+
+    pub(crate) fn from_parts(
+        transport: Arc<dyn Transport>,
+        endpoints: Endpoints,
+        default_headers: HeaderMap,
+        retry_policy: RetryPolicy,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        state_file: Option<PathBuf>,
+    ) -> Self {
         AppointmentSession {
-            client,
+            transport,
+            endpoints,
+            retry_policy,
+            rate_limiter,
+            default_headers,
+            state_file,
             state: Arc::new(Mutex::new(SessionState::default())),
         }
     }
 
-    pub async fn ensure_init(&self) -> anyhow::Result<()> {
+    pub(crate) fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    pub async fn ensure_init(&self) -> Result<()> {
+        self.ensure_init_generation().await?;
+        Ok(())
+    }
+
+    /// Initializes the session if needed, and returns the generation of the
+    /// initialized session.
+    ///
+    /// If the session has a state file, the state saved in it is restored the
+    /// first time instead, without checking whether it is still valid: an
+    /// expired session is detected on the first request, which initializes it
+    /// again. The state is saved to the file every time the session is
+    /// initialized.
+    async fn ensure_init_generation(&self) -> Result<u64> {
         let mut state = self.state.lock().await;
+        if !state.init
+            && !state.restore_attempted
+            && let Some(path) = &self.state_file
+        {
+            state.restore_attempted = true;
+            match restore_session(self.transport.as_ref(), &self.endpoints.base, path) {
+                Ok(Some(restored)) => {
+                    debug!(
+                        "Restored session state from {} saved {:?} ago",
+                        path.display(),
+                        restored.age
+                    );
+                    if restored.initialized {
+                        state.init = true;
+                        state.generation += 1;
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => warn!(
+                    "Couldn't restore session state from {}: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+
         if !state.init {
-            self.init_session().await.context("Init session")?;
-            self.auth_anonymous().await.context("Anonynous Auth")?;
+            self.init_session().await?;
+            self.auth_anonymous().await?;
             state.init = true;
+            state.generation += 1;
+
+            if let Some(path) = &self.state_file
+                && let Err(err) =
+                    save_session(self.transport.as_ref(), &self.endpoints.base, path, true)
+            {
+                warn!("Couldn't save session state to {}: {}", path.display(), err);
+            }
         }
-        Ok(())
+        Ok(state.generation)
+    }
+
+    /// Saves the cookies of the session and whether it is initialized to the
+    /// given file, so it can be restored by another session through
+    /// [`AppointmentSession::restore_state`] or
+    /// [`AppointmentSessionBuilder::state_file`].
+    pub async fn save_state(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> std::result::Result<(), PersistError> {
+        let state = self.state.lock().await;
+        save_session(
+            self.transport.as_ref(),
+            &self.endpoints.base,
+            path.as_ref(),
+            state.init,
+        )
+    }
+
+    /// Restores the state saved by [`AppointmentSession::save_state`] to the
+    /// given file. Returns whether there was a state to restore.
+    ///
+    /// The restored state is not checked to still be valid. If it has expired
+    /// on the server side, the session is initialized again on the first
+    /// request that detects it.
+    pub async fn restore_state(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> std::result::Result<bool, PersistError> {
+        let mut state = self.state.lock().await;
+        let Some(restored) =
+            restore_session(self.transport.as_ref(), &self.endpoints.base, path.as_ref())?
+        else {
+            return Ok(false);
+        };
+
+        state.restore_attempted = true;
+        state.init = restored.initialized;
+        state.generation += 1;
+        Ok(true)
     }
 
-    async fn init_session(&self) -> anyhow::Result<()> {
-        Self::trace_send_request(self.client.get(BASE_URL.clone()))
-            .await?
-            .error_for_status()?;
+    /// Marks the session as not initialized, unless it has already been
+    /// initialized again since the given generation.
+    async fn reset_session(&self, generation: u64) {
+        let mut state = self.state.lock().await;
+        if state.generation == generation {
+            state.init = false;
+        }
+    }
+
+    async fn init_session(&self) -> Result<()> {
+        self.send_request(
+            "init_session",
+            TransportRequest::get(self.endpoints.base.clone()),
+            ExpectedResponse::Any,
+        )
+        .await?;
         Ok(())
     }
 
-    async fn auth_anonymous(&self) -> anyhow::Result<()> {
-        Self::trace_send_request(
-            self.client
-                .post(ENDPOINT_AJAX_AUTH.clone())
+    async fn auth_anonymous(&self) -> Result<()> {
+        self.send_request(
+            "auth_anonymous",
+            TransportRequest::post(self.endpoints.ajax_auth.clone())
                 .body("")
-                .header(CONTENT_LENGTH, 0), // Must send always the Content-Length and set it to zero.
+                .header(CONTENT_LENGTH, HeaderValue::from(0)), // Must send always the Content-Length and set it to zero.
+            ExpectedResponse::Any,
         )
-        .await
-        .with_context(|| format!("POST request to {}", ENDPOINT_AJAX_AUTH.as_str()))?
-        .error_for_status()?;
+        .await?;
         Ok(())
     }
 
-    async fn trace_body_and_error_for_response(
+    /// Sends a request that requires an initialized session. If the session
+    /// turns out to be expired, it is initialized again and the request is
+    /// retried once.
+    async fn send_session_request(
+        &self,
         operation: &str,
-        resp: Response,
-    ) -> anyhow::Result<String> {
-        debug!("{} status code: {}", operation, resp.status());
-        let body = resp.text().await.context("Reading response body")?;
-
-        trace!("{} response body: {}", operation, body);
-        Ok(body)
+        request: TransportRequest,
+        expected: ExpectedResponse,
+    ) -> Result<String> {
+        let generation = self.ensure_init_generation().await?;
+        match self
+            .send_request(operation, request.clone(), expected)
+            .await
+        {
+            Err(Error::SessionExpired { url, .. }) => {
+                warn!(
+                    "{}: session expired on request to {}, re-authenticating",
+                    operation, url
+                );
+                self.reset_session(generation).await;
+                self.ensure_init_generation().await?;
+                self.send_request(operation, request, expected).await
+            }
+            result => result,
+        }
     }
 
-    fn trace_send_request(
-        request: RequestBuilder,
-    ) -> impl Future<Output = Result<Response, reqwest::Error>> {
-        log::trace!("Sending request: {:?}", &request);
-        return request.send();
+    /// Sends the given request through the session transport, retrying it
+    /// according to the session retry policy, and returns the body of the
+    /// response if it was successful.
+    pub(crate) async fn send_request(
+        &self,
+        operation: &str,
+        request: TransportRequest,
+        expected: ExpectedResponse,
+    ) -> Result<String> {
+        let mut attempt = 1;
+        loop {
+            match self
+                .send_request_once(operation, request.clone(), expected)
+                .await
+            {
+                Err(err) if self.retry_policy.should_retry(&err, attempt) => {
+                    let backoff = self.retry_policy.retry_delay(&err, attempt);
+                    warn!(
+                        "{}: attempt {}/{} failed, retrying in {:?}: {}",
+                        operation, attempt, self.retry_policy.max_attempts, backoff, err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => {
+                    if attempt > 1 {
+                        info!(
+                            "{}: finished after {} retries (success: {})",
+                            operation,
+                            attempt - 1,
+                            result.is_ok()
+                        );
+                    }
+                    return result;
+                }
+            }
+        }
     }
 
-    async fn trace_json_body_and_error_for_response<T: for<'de> serde::de::Deserialize<'de>>(
+    async fn send_request_once(
+        &self,
         operation: &str,
-        resp: Response,
-    ) -> anyhow::Result<T> {
-        let body = Self::trace_body_and_error_for_response(operation, resp).await?;
-        Ok(serde_json::from_str(&body).context("Parsing response body")?)
+        mut request: TransportRequest,
+        expected: ExpectedResponse,
+    ) -> Result<String> {
+        for (name, value) in self.default_headers.iter() {
+            if !request.headers.contains_key(name) {
+                request.headers.insert(name, value.clone());
+            }
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+
+        trace!("Sending request: {:?}", &request);
+        let url = request.url.clone();
+        let resp = self
+            .transport
+            .send(request)
+            .await
+            .map_err(|source| Error::Transport {
+                url: url.to_string(),
+                source,
+            })?;
+
+        debug!("{} status code: {}", operation, resp.status);
+        trace!("{} response body: {}", operation, resp.body);
+
+        // Checked first, since these pages may also be served through a
+        // redirect that would otherwise be taken as an expired session. Only
+        // done for the pages of the appointments website, since the phrases
+        // looked for may be found in regular pages of other websites, as the
+        // office information pages.
+        if url.as_str().starts_with(self.endpoints.base.as_str())
+            && let Some(unavailable) = classify_response(&resp)
+        {
+            return Err(Error::ServiceUnavailable {
+                url: url.to_string(),
+                reason: unavailable.reason,
+                retry_after: unavailable.retry_after,
+                body: truncate_body(&resp.body),
+            });
+        }
+
+        // Checked before looking for an expired session, since error pages
+        // served by proxies are HTML documents as well, and would otherwise
+        // be taken as an expired session on JSON endpoints.
+        if !resp.status.is_success() {
+            let retry_after = matches!(
+                resp.status,
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+            )
+            .then(|| retry_after_header(&resp))
+            .flatten();
+            return Err(Error::HttpStatus {
+                url: url.to_string(),
+                status: resp.status,
+                retry_after,
+                body: truncate_body(&resp.body),
+            });
+        }
+
+        if Self::is_session_expired_response(&url, &resp, expected) {
+            return Err(Error::SessionExpired {
+                url: url.to_string(),
+                body: truncate_body(&resp.body),
+            });
+        }
+        Ok(resp.body)
+    }
+
+    /// Checks whether the server answered with something that doesn't belong
+    /// to the requested endpoint, which happens when the session cookies are
+    /// no longer valid: the server either redirects to its landing page or
+    /// serves an HTML page where a JSON document is expected.
+    fn is_session_expired_response(
+        request_url: &Url,
+        resp: &TransportResponse,
+        expected: ExpectedResponse,
+    ) -> bool {
+        match expected {
+            ExpectedResponse::Any => false,
+            ExpectedResponse::Html => resp.url.path() != request_url.path(),
+            ExpectedResponse::Json => {
+                resp.url.path() != request_url.path() || resp.body.trim_start().starts_with('<')
+            }
+        }
     }
 
-    fn read_office_optional<'a, T: DeserializeOwned>(body: &'a str) -> anyhow::Result<Option<T>> {
-        let read = serde_json::from_str::<Map<String, Value>>(body)?;
+    fn read_office_optional<T: DeserializeOwned>(url: &Url, body: &str) -> Result<Option<T>> {
+        let read = serde_json::from_str::<Map<String, Value>>(body)
+            .map_err(|err| Error::parse(url, err, body))?;
         let Some(office_id) = read.get("idOficina") else {
-            bail!("Required office id not present in json");
+            return Err(Error::parse(
+                url,
+                "Required office id not present in json",
+                body,
+            ));
         };
 
         let Some(office_id_num) = office_id.as_u64() else {
-            bail!("Office id is not a valid numeric value");
+            return Err(Error::parse(
+                url,
+                "Office id is not a valid numeric value",
+                body,
+            ));
         };
 
         if office_id_num == 0 {
             Ok(None)
         } else {
-            Ok(Some(serde_json::from_value(Value::Object(read))?))
+            serde_json::from_value(Value::Object(read))
+                .map(Some)
+                .map_err(|err| Error::parse(url, err, body))
         }
     }
 
     pub async fn get_office_closest_appointment(
         &self,
         procedure: ProcedureId,
-    ) -> anyhow::Result<Option<NetOfficeModel>> {
-        self.ensure_init().await?;
-
-        let resp = Self::trace_send_request(
-            self.client
-                .post(ENDPOINT_CLOSEST_APPOINTMENT_OFFICE.clone())
-                .header(ACCEPT, "application/json")
-                .form(&HashMap::from([("idTipoTramite", procedure.0)])),
-        )
-        .await?;
-
-        let body =
-            Self::trace_body_and_error_for_response("get_office_closest_appointment", resp).await?;
-        Ok(Self::read_office_optional(&body)?)
+    ) -> Result<Option<NetOfficeModel>> {
+        let body = self
+            .send_session_request(
+                "get_office_closest_appointment",
+                TransportRequest::post(self.endpoints.closest_appointment_office.clone())
+                    .header(ACCEPT, HeaderValue::from_static("application/json"))
+                    .form(&HashMap::from([("idTipoTramite", procedure.0)])),
+                ExpectedResponse::Json,
+            )
+            .await?;
+        Self::read_office_optional(&self.endpoints.closest_appointment_office, &body)
     }
 
     pub async fn get_appointments_for_office(
         &self,
         office: OfficeId,
         procedure_office_id: ProcedureOfficeId,
-    ) -> anyhow::Result<Vec<NaiveDate>> {
-        self.ensure_init().await?;
-
+    ) -> Result<AppointmentDays> {
         let id_office = office.0.to_string();
         let id_procedure = procedure_office_id.0.to_string();
 
@@ -268,165 +607,917 @@ impl AppointmentSession {
             ("numeroDocumento", ""),
         ]);
 
-        let resp = Self::trace_send_request(
-            self.client
-                .post(ENDPOINT_OFFICE_APPOINTMENTS.clone())
-                .form(&request),
-        )
-        .await?;
+        let body = self
+            .send_session_request(
+                "get_appointments_for_office",
+                TransportRequest::post(self.endpoints.office_appointments.clone()).form(&request),
+                ExpectedResponse::Html,
+            )
+            .await?;
+        self.read_appointment_days(&body)
+    }
+
+    /// Same as [`AppointmentSession::get_appointments_for_office`], but going
+    /// through the procedure route of the website, which identifies the
+    /// procedure by its [`ProcedureId`] instead of the id it has in each
+    /// office.
+    ///
+    /// If no office is given, the server picks the office with the closest
+    /// appointment for the procedure, as the website does. The office and the
+    /// [`ProcedureOfficeId`] the server resolved are read back from the page
+    /// when present, so the latter can be used for fetching the slots of each
+    /// day.
+    ///
+    /// Each call queries a single office. See
+    /// [`AppointmentSession::query_procedure_appointments`] for querying
+    /// several of them.
+    pub async fn get_appointments_for_procedure(
+        &self,
+        procedure: ProcedureId,
+        office: Option<OfficeId>,
+    ) -> Result<ProcedureAppointments> {
+        let id_procedure = procedure.0.to_string();
+        let id_office = office
+            .map(|office| office.0.to_string())
+            .unwrap_or_default();
+
+        let request = HashMap::from([
+            ("valido", "true"),
+            ("ruta", "tramite"),
+            ("idCiudadanoCitaAnterior", ""),
+            ("idOficinaEdicion", ""),
+            ("idServicioEdicion", ""),
+            ("usaVariablesEdicion", ""),
+            ("esModificacion", ""),
+            ("origen", ""),
+            ("idFamiliaCita", &id_procedure),
+            ("idOficina", &id_office),
+            ("idServicio", ""),
+            ("idTipoDocumentoUsuarioAut", ""),
+            ("numeroDocumento", ""),
+        ]);
 
-        let body =
-            Self::trace_body_and_error_for_response("get_appointments_for_office", resp).await?;
+        let body = self
+            .send_session_request(
+                "get_appointments_for_procedure",
+                TransportRequest::post(self.endpoints.office_appointments.clone()).form(&request),
+                ExpectedResponse::Html,
+            )
+            .await?;
+        let days = self.read_appointment_days(&body)?;
+
+        let html = Html::parse_document(&body);
+        let read_input = |selector: &Selector| {
+            html.select(selector)
+                .filter_map(|input| input.attr("value"))
+                .find_map(|value| u32::from_str(value.trim()).ok())
+        };
+
+        Ok(ProcedureAppointments {
+            office_id: office.or_else(|| read_input(&SELECTOR_INPUT_OFFICE_ID).map(OfficeId)),
+            procedure_office_id: read_input(&SELECTOR_INPUT_PROCEDURE_OFFICE_ID)
+                .map(ProcedureOfficeId),
+            days,
+        })
+    }
+
+    /// Reads the days with available appointments from the page returned by
+    /// the office appointments endpoint.
+    fn read_appointment_days(&self, body: &str) -> Result<AppointmentDays> {
         if body.contains("Las citas disponibles en esta oficina han sido reservadas recientemente")
         {
-            return Ok(Vec::new());
+            return Ok(AppointmentDays::RecentlyBooked);
         }
 
-        if let Some(caps) = RE_AVAILABLE_APPOINTMENTS.captures(&body) {
-            let appointments = serde_json::from_str::<Vec<NetAppointment>>(&caps[1])?;
-            return Ok(appointments
-                .into_iter()
-                .map(|app| {
-                    NaiveDate::from_ymd_opt(app.year as i32, app.month as u32, app.day as u32)
-                        .unwrap()
-                })
-                .collect());
-        } else {
-            bail!("Response body didn't match expected response for get_appointments_for_office")
-        }
+        let Some(caps) = RE_AVAILABLE_APPOINTMENTS.captures(body) else {
+            return Err(Error::unexpected_page(
+                &self.endpoints.office_appointments,
+                "Available appointments not found in page",
+                body,
+            ));
+        };
+
+        let appointments = serde_json::from_str::<Vec<NetAppointment>>(&caps[1])
+            .map_err(|err| Error::parse(&self.endpoints.office_appointments, err, body))?;
+        appointments
+            .into_iter()
+            .map(|app| {
+                NaiveDate::from_ymd_opt(app.year as i32, app.month as u32, app.day as u32)
+                    .ok_or_else(|| {
+                        Error::parse(
+                            &self.endpoints.office_appointments,
+                            format!(
+                                "Invalid appointment date: {}-{}-{}",
+                                app.year, app.month, app.day
+                            ),
+                            body,
+                        )
+                    })
+            })
+            .collect::<Result<_>>()
+            .map(AppointmentDays::Available)
     }
 
     pub async fn get_available_appointment_slots_for_office_day(
         &self,
         procedure_office_id: ProcedureOfficeId,
         day: NaiveDate,
-    ) -> anyhow::Result<impl Iterator<Item = DateTime<chrono_tz::Tz>>> {
-        fn build_slot_dt(raw_time: &str, day: NaiveDate) -> DateTime<chrono_tz::Tz> {
-            let time = NaiveTime::parse_from_str(raw_time, "%H:%M").unwrap();
-            chrono_tz::Tz::from_local_datetime(
-                &chrono_tz::Europe::Madrid,
-                &NaiveDateTime::new(day, time),
-            )
-            .unwrap()
+    ) -> Result<impl Iterator<Item = DateTime<chrono_tz::Tz>>> {
+        let day_slots = self
+            .get_appointment_slots_for_office_day(procedure_office_id, day)
+            .await?;
+        Ok(day_slots.available_slots().collect::<Vec<_>>().into_iter())
+    }
+
+    /// Returns all the slots of the given day, including the ones that are
+    /// already booked, grouped by hour and minute as returned by the server.
+    pub async fn get_appointment_slots_for_office_day(
+        &self,
+        procedure_office_id: ProcedureOfficeId,
+        day: NaiveDate,
+    ) -> Result<DaySlots> {
+        self.query_appointment_slots_for_office_day(
+            procedure_office_id,
+            day,
+            &DaySlotsQuery::default(),
+        )
+        .await
+    }
+
+    /// Same as [`AppointmentSession::get_appointment_slots_for_office_day`],
+    /// with the given query parameters.
+    ///
+    /// When querying the last day with appointments, the server may split its
+    /// slots across several responses. In such case, the following pages are
+    /// requested until no new slots are returned.
+    pub async fn query_appointment_slots_for_office_day(
+        &self,
+        procedure_office_id: ProcedureOfficeId,
+        day: NaiveDate,
+        query: &DaySlotsQuery,
+    ) -> Result<DaySlots> {
+        let mut hours = self
+            .get_appointment_slots_page(procedure_office_id, day, query, 0)
+            .await?;
+
+        if query.last_day {
+            // The offset of the following page is the number of hourly groups
+            // returned by the server so far, including any left empty.
+            let mut offset = hours.len();
+            for _ in 1..MAX_DAY_SLOTS_PAGES {
+                let Some(last_time) = hours
+                    .iter()
+                    .flat_map(HourlySlots::slots)
+                    .map(|slot| slot.time)
+                    .max()
+                else {
+                    break;
+                };
+
+                let page = self
+                    .get_appointment_slots_page(procedure_office_id, day, query, offset)
+                    .await?;
+                offset += page.len();
+
+                // Only keep the groups following the ones already received, in
+                // case the server ignores the offset and returns them again.
+                let new_hours: Vec<_> = page
+                    .into_iter()
+                    .filter(|hour| {
+                        hour.slots()
+                            .next()
+                            .is_some_and(|slot| slot.time > last_time)
+                    })
+                    .collect();
+                if new_hours.is_empty() {
+                    break;
+                }
+
+                debug!(
+                    "query_appointment_slots_for_office_day: {} more hourly groups received for {}",
+                    new_hours.len(),
+                    day
+                );
+                hours.extend(new_hours);
+            }
         }
 
-        self.ensure_init().await?;
+        Ok(DaySlots { day, hours })
+    }
+
+    /// Requests a single page of slots of a day, starting at the given number
+    /// of hourly groups already received.
+    async fn get_appointment_slots_page(
+        &self,
+        procedure_office_id: ProcedureOfficeId,
+        day: NaiveDate,
+        query: &DaySlotsQuery,
+        offset: usize,
+    ) -> Result<Vec<HourlySlots>> {
         let id_procedure = procedure_office_id.0.to_string();
         let current_ts = Utc::now().timestamp_millis().to_string();
         let search_day = day.format("%d/%m/%Y").to_string();
+        let last_day = query.last_day.to_string();
+        let offset = offset.to_string();
+        let attention_type = query.attention_type.code().to_string();
 
-        let req = self
-            .client
-            .get(ENDPOINT_DAY_APPOINTMENT_SLOTS.clone())
-            .query(&[
-                ("idTramite", id_procedure.as_str()),
-                ("dia", &search_day),
-                ("esUltimoDiaHuecos", "false"),
-                ("nh", "0"),
-                ("time", &current_ts),
-                ("idTipoAtencion", "1"),
-            ]);
-        let resp = Self::trace_send_request(req).await?;
-
-        let hourly_slots = Self::trace_json_body_and_error_for_response::<
-            Vec<NetAppointmentHourlySlots>,
-        >("get_available_appointment_slots_for_office_day", resp)
-        .await?;
-        Ok(hourly_slots
-            .into_iter()
-            .flat_map(|hourly_slots| hourly_slots.slot_sets)
-            .flat_map(|minute_slots| minute_slots.slots)
-            .filter(|slot| slot.available)
-            .map(move |slot| build_slot_dt(&slot.raw_time, day)))
+        let req = TransportRequest::get(self.endpoints.day_appointment_slots.clone()).query(&[
+            ("idTramite", id_procedure.as_str()),
+            ("dia", &search_day),
+            ("esUltimoDiaHuecos", &last_day),
+            ("nh", &offset),
+            ("time", &current_ts),
+            ("idTipoAtencion", &attention_type),
+        ]);
+        let url = &self.endpoints.day_appointment_slots;
+        let body = self
+            .send_session_request("get_appointment_slots_page", req, ExpectedResponse::Json)
+            .await?;
+        let hourly_slots = serde_json::from_str::<Vec<NetAppointmentHourlySlots>>(&body)
+            .map_err(|err| Error::parse(url, err, &body))?;
+
+        // Slots with a time that doesn't exist in Madrid are skipped, so a
+        // single one doesn't discard the whole day. The hourly groups are kept
+        // even if left empty, since the following pages are requested by their
+        // count.
+        let mut hours = Vec::with_capacity(hourly_slots.len());
+        for hourly_slots in hourly_slots {
+            let mut minutes = Vec::with_capacity(hourly_slots.slot_sets.len());
+            for minute_slots in hourly_slots.slot_sets {
+                let mut slots = Vec::with_capacity(minute_slots.slots.len());
+                for slot in minute_slots.slots {
+                    let time = slot_datetime(day, &slot.raw_time)
+                        .map_err(|reason| Error::parse(url, reason, &body))?;
+                    match time {
+                        Some(time) => slots.push(Slot {
+                            time,
+                            available: slot.available,
+                        }),
+                        None => warn!(
+                            "get_appointment_slots_page: Skipping slot {} {}, which doesn't exist in Madrid",
+                            day, slot.raw_time
+                        ),
+                    }
+                }
+                minutes.push(MinuteSlots { slots });
+            }
+            hours.push(HourlySlots { minutes });
+        }
+
+        Ok(hours)
     }
 
-    pub async fn list_available_procedures(&self) -> anyhow::Result<Vec<NetProcedureModel>> {
-        self.ensure_init().await?;
+    pub async fn list_available_procedures(&self) -> Result<Vec<NetProcedureModel>> {
+        let body = self
+            .send_session_request(
+                "list_available_procedures",
+                TransportRequest::get(self.endpoints.appointments_by_procedure_landing.clone()),
+                ExpectedResponse::Html,
+            )
+            .await?;
+
+        Ok(Self::read_grouped_combobox(
+            "list_available_procedures",
+            &self.endpoints.appointments_by_procedure_landing,
+            &body,
+            &SELECTOR_PROCEDURES_COMBOBOX,
+            "select#selectTramites",
+        )?
+        .into_iter()
+        .map(|option| NetProcedureModel {
+            procedure_category: option.group,
+            procedure_name: option.name,
+            procedure_id: ProcedureId(option.value),
+        })
+        .collect())
+    }
 
-        let resp = Self::trace_send_request(
-            self.client
-                .get(ENDPOINT_APPOINTMENTS_BY_PROCEDURE_LANDING.clone()),
-        )
-        .await?;
+    pub async fn list_offices(&self) -> Result<Vec<NetOfficeBasicInfoModel>> {
+        let body = self
+            .send_session_request(
+                "list_offices",
+                TransportRequest::get(self.endpoints.appointments_by_office_landing.clone()),
+                ExpectedResponse::Html,
+            )
+            .await?;
 
-        let body =
-            Self::trace_body_and_error_for_response("list_available_procedures", resp).await?;
+        Ok(Self::read_grouped_combobox(
+            "list_offices",
+            &self.endpoints.appointments_by_office_landing,
+            &body,
+            &SELECTOR_OFFICES_COMBOBOX,
+            "select#selectOficinas",
+        )?
+        .into_iter()
+        .map(|option| NetOfficeBasicInfoModel {
+            name: option.name,
+            group: option.group,
+            id: OfficeId(option.value),
+        })
+        .collect())
+    }
 
-        // TODO remove unwraps
-        let html = Html::parse_document(&body);
-        let select = html.select(&SELECTOR_PROCEDURES_COMBOBOX).next().unwrap();
-
-        Ok(select
-            .select(&SELECTOR_OPTGROUP)
-            .flat_map(|optgroup| {
-                let label = optgroup.attr("label").unwrap();
-                debug!("list_available_procedures: Group: {}", label);
-                optgroup.select(&SELECTOR_OPTION).map(|option| {
-                    let procedure_name = option.inner_html();
-                    let procedure_id = option.attr("value").unwrap();
-                    debug!(
-                        "list_available_procedures:   Procedure: {}; {}",
-                        procedure_name, procedure_id
+    /// Reads the options of a combobox whose options are grouped in
+    /// optgroups, as the ones used for listing the offices and procedures.
+    ///
+    /// Fails if the combobox is not present in the page. Options without a
+    /// numeric value are skipped, and the ones in groups without a label are
+    /// kept with an empty group.
+    fn read_grouped_combobox(
+        operation: &str,
+        url: &Url,
+        body: &str,
+        selector: &Selector,
+        element_name: &str,
+    ) -> Result<Vec<ComboboxOption>> {
+        let html = Html::parse_document(body);
+        let Some(select) = html.select(selector).next() else {
+            return Err(Error::unexpected_page(
+                url,
+                format!("Element {} not found in page", element_name),
+                body,
+            ));
+        };
+
+        let mut options = Vec::new();
+        for optgroup in select.select(&SELECTOR_OPTGROUP) {
+            let label = optgroup.attr("label").unwrap_or_else(|| {
+                warn!(
+                    "{}: Reading group without label in {} as an empty group",
+                    operation, element_name
+                );
+                ""
+            });
+            debug!("{}: Group: {}", operation, label);
+
+            for option in optgroup.select(&SELECTOR_OPTION) {
+                let name = option.inner_html();
+                let Some(value) = option.attr("value") else {
+                    warn!("{}: Skipping option without value: {}", operation, name);
+                    continue;
+                };
+
+                let Ok(value) = u32::from_str(value.trim()) else {
+                    warn!(
+                        "{}: Skipping option with non-numeric value: {}; {}",
+                        operation, name, value
                     );
+                    continue;
+                };
 
-                    NetProcedureModel {
-                        procedure_category: label.to_string(),
-                        procedure_name,
-                        procedure_id: ProcedureId(FromStr::from_str(procedure_id).unwrap()),
-                    }
-                })
+                debug!("{}:   Option: {}; {}", operation, name, value);
+                options.push(ComboboxOption {
+                    group: label.to_string(),
+                    name,
+                    value,
+                });
+            }
+        }
+
+        Ok(options)
+    }
+
+    pub async fn get_office_details(&self, office_id: OfficeId) -> Result<Option<NetOfficeModel>> {
+        let office_id_str = office_id.0.to_string();
+
+        let body = self
+            .send_request(
+                "get_office_details",
+                TransportRequest::get(self.endpoints.office_info.clone())
+                    .query(&[("idOficina", office_id_str)]),
+                ExpectedResponse::Any,
+            )
+            .await?;
+        Self::read_office_optional(&self.endpoints.office_info, &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::testing::{FakeTransport, TestDir, param, response, slots_page};
+
+    const OFFICES_PAGE: &str = include_str!("../tests/fixtures/offices.html");
+    const APPOINTMENTS_PAGE: &str = include_str!("../tests/fixtures/office_appointments.html");
+    const RECENTLY_BOOKED_PAGE: &str =
+        include_str!("../tests/fixtures/office_recently_booked.html");
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Serves the slots of a day through a transport paging them as the
+    /// server does: the closure returns the hourly groups of the page for the
+    /// given offset, each with a slot at the given time, or none if empty.
+    fn paged_slots_transport(
+        page: impl Fn(usize) -> Vec<Option<String>> + Send + Sync + 'static,
+    ) -> FakeTransport {
+        FakeTransport::new(move |request| {
+            if !request.url.path().ends_with("/franjasDia.do") {
+                return response(&request.url, "");
+            }
+            let offset = param(request, "nh").unwrap().parse().unwrap();
+            response(&request.url, slots_page(page(offset)))
+        })
+    }
+
+    async fn query_last_day(transport: &FakeTransport) -> (Vec<String>, Vec<usize>) {
+        let query = DaySlotsQuery {
+            last_day: true,
+            ..Default::default()
+        };
+        let day_slots = transport
+            .session()
+            .query_appointment_slots_for_office_day(
+                ProcedureOfficeId(1290),
+                date(2026, 11, 12),
+                &query,
+            )
+            .await
+            .unwrap();
+
+        let times = day_slots
+            .available_slots()
+            .map(|time| time.format("%H:%M").to_string())
+            .collect();
+        let offsets = transport
+            .requests()
+            .iter()
+            .filter(|request| request.url.path().ends_with("/franjasDia.do"))
+            .map(|request| {
+                assert_eq!(param(request, "esUltimoDiaHuecos").unwrap(), "true");
+                param(request, "nh").unwrap().parse().unwrap()
             })
-            .collect())
+            .collect();
+        (times, offsets)
     }
 
-    pub async fn list_offices(&self) -> anyhow::Result<Vec<NetOfficeBasicInfoModel>> {
-        self.ensure_init().await?;
+    #[tokio::test]
+    async fn list_offices_reads_grouped_options() {
+        let transport = FakeTransport::serving("/oficina.do", OFFICES_PAGE);
 
-        let resp = Self::trace_send_request(
-            self.client
-                .get(ENDPOINT_APPOINTMENTS_BY_OFFICE_LANDING.clone()),
-        )
-        .await?;
+        let offices = transport.session().list_offices().await.unwrap();
+        let offices: Vec<_> = offices
+            .iter()
+            .map(|office| (office.id.0, office.group.as_str(), office.name.as_str()))
+            .collect();
+        assert_eq!(
+            offices,
+            [
+                (1, "LINEA MADRID", "Línea Madrid Centro"),
+                (2, "LINEA MADRID", "Línea Madrid Chamberí"),
+                (
+                    17,
+                    "AGENCIA TRIBUTARIA MADRID",
+                    "Oficina de Atención Integral al Contribuyente"
+                ),
+                (99, "", "Oficina Sin Grupo"),
+            ]
+        );
+    }
 
-        let body = Self::trace_body_and_error_for_response("list_offices", resp).await?;
+    #[tokio::test]
+    async fn list_offices_fails_without_combobox() {
+        let transport = FakeTransport::with_bodies(|_| "<html><body></body></html>".to_string());
 
-        // TODO remove unwraps
+        let err = transport.session().list_offices().await.unwrap_err();
+        assert!(matches!(err, Error::UnexpectedPage { .. }), "{:?}", err);
+    }
 
-        let r = Html::parse_document(&body);
+    #[tokio::test]
+    async fn get_appointments_for_office_reads_days() {
+        let transport = FakeTransport::serving("/horarioOficina.do", APPOINTMENTS_PAGE);
 
-        let select = r.select(&SELECTOR_OFFICES_COMBOBOX).next().unwrap();
+        let days = transport
+            .session()
+            .get_appointments_for_office(OfficeId(1), ProcedureOfficeId(1290))
+            .await
+            .unwrap();
+        assert_eq!(
+            days,
+            AppointmentDays::Available(vec![
+                date(2026, 11, 3),
+                date(2026, 11, 4),
+                date(2026, 11, 12)
+            ])
+        );
+    }
 
-        Ok(select
-            .select(&SELECTOR_OPTGROUP)
-            .flat_map(|optgroup| {
-                let office_category_name = optgroup.attr("label").unwrap();
-                optgroup.select(&SELECTOR_OPTION).map(|option| {
-                    let office_name = option.inner_html();
-                    let office_id = option.attr("value").unwrap();
-                    NetOfficeBasicInfoModel {
-                        name: office_name,
-                        group: office_category_name.to_string(),
-                        id: OfficeId(FromStr::from_str(office_id).unwrap()),
-                    }
-                })
+    #[tokio::test]
+    async fn get_appointments_for_office_detects_recently_booked() {
+        let transport = FakeTransport::serving("/horarioOficina.do", RECENTLY_BOOKED_PAGE);
+
+        let days = transport
+            .session()
+            .get_appointments_for_office(OfficeId(1), ProcedureOfficeId(1290))
+            .await
+            .unwrap();
+        assert_eq!(days, AppointmentDays::RecentlyBooked);
+    }
+
+    #[tokio::test]
+    async fn get_appointments_for_procedure_reads_resolved_office() {
+        for office in [None, Some(OfficeId(7))] {
+            let transport = FakeTransport::serving("/horarioOficina.do", APPOINTMENTS_PAGE);
+
+            let appointments = transport
+                .session()
+                .get_appointments_for_procedure(ProcedureId(321), office)
+                .await
+                .unwrap();
+            assert_eq!(
+                appointments,
+                ProcedureAppointments {
+                    office_id: Some(office.unwrap_or(OfficeId(1))),
+                    procedure_office_id: Some(ProcedureOfficeId(1290)),
+                    days: AppointmentDays::Available(vec![
+                        date(2026, 11, 3),
+                        date(2026, 11, 4),
+                        date(2026, 11, 12)
+                    ]),
+                }
+            );
+
+            let requests = transport.requests();
+            let request = requests
+                .iter()
+                .find(|request| request.url.path().ends_with("/horarioOficina.do"))
+                .unwrap();
+            assert_eq!(param(request, "ruta").unwrap(), "tramite");
+            assert_eq!(param(request, "idFamiliaCita").unwrap(), "321");
+            assert_eq!(
+                param(request, "idOficina").unwrap(),
+                office
+                    .map(|office| office.0.to_string())
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    #[test]
+    fn read_appointment_days_rejects_invalid_dates() {
+        let session = FakeTransport::with_bodies(|_| String::new()).session();
+        let body = r#"<script>JSON.parse('[{"dia":31,"mes":2,"ano":2026}]')</script>"#;
+
+        let err = session.read_appointment_days(body).unwrap_err();
+        assert!(matches!(err, Error::Parse { .. }), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn slots_in_daylight_saving_gap_are_skipped() {
+        let transport = FakeTransport::serving(
+            "/franjasDia.do",
+            r#"[
+                {"franjasMinuto": [{"huecos": [{"hora": "02:30", "disponible": true}]}]},
+                {"franjasMinuto": [{"huecos": [{"hora": "03:00", "disponible": true}]}]}
+            ]"#,
+        );
+
+        let day_slots = transport
+            .session()
+            .get_appointment_slots_for_office_day(ProcedureOfficeId(1290), date(2026, 3, 29))
+            .await
+            .unwrap();
+        let times: Vec<_> = day_slots
+            .available_slots()
+            .map(|time| time.format("%H:%M %Z").to_string())
+            .collect();
+        assert_eq!(times, ["03:00 CEST"]);
+        assert_eq!(day_slots.hours.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn repeated_slot_times_are_their_first_occurrence() {
+        let transport = FakeTransport::serving("/franjasDia.do", slots_page([Some("02:30")]));
+
+        let day_slots = transport
+            .session()
+            .get_appointment_slots_for_office_day(ProcedureOfficeId(1290), date(2026, 10, 25))
+            .await
+            .unwrap();
+        let times: Vec<_> = day_slots
+            .available_slots()
+            .map(|time| time.format("%H:%M %Z").to_string())
+            .collect();
+        assert_eq!(times, ["02:30 CEST"]);
+    }
+
+    #[tokio::test]
+    async fn malformed_slot_times_are_parse_errors() {
+        for raw_time in ["9h30", "25:00"] {
+            let transport = FakeTransport::serving(
+                "/franjasDia.do",
+                format!(
+                    r#"[{{"franjasMinuto": [{{"huecos": [
+                        {{"hora": "09:00", "disponible": true}},
+                        {{"hora": "{}", "disponible": true}}
+                    ]}}]}}]"#,
+                    raw_time
+                ),
+            );
+
+            let err = transport
+                .session()
+                .get_appointment_slots_for_office_day(ProcedureOfficeId(1290), date(2026, 3, 30))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::Parse { .. }),
+                "{}: {:?}",
+                raw_time,
+                err
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn last_day_pages_are_merged() {
+        let hours = [
+            Some("09:00"),
+            Some("10:00"),
+            None,
+            Some("11:00"),
+            Some("12:00"),
+        ];
+        let transport = paged_slots_transport(move |offset| {
+            hours
+                .iter()
+                .skip(offset)
+                .take(2)
+                .map(|time| time.map(str::to_string))
+                .collect()
+        });
+
+        let (times, offsets) = query_last_day(&transport).await;
+        assert_eq!(times, ["09:00", "10:00", "11:00", "12:00"]);
+        assert_eq!(offsets, [0, 2, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn last_day_pages_ignoring_the_offset_are_not_duplicated() {
+        let transport =
+            paged_slots_transport(|_| vec![Some("09:00".to_string()), Some("10:00".to_string())]);
+
+        let (times, offsets) = query_last_day(&transport).await;
+        assert_eq!(times, ["09:00", "10:00"]);
+        assert_eq!(offsets, [0, 2]);
+    }
+
+    #[tokio::test]
+    async fn last_day_paging_is_bounded() {
+        let transport = paged_slots_transport(|offset| vec![Some(format!("{:02}:00", 8 + offset))]);
+
+        let (times, offsets) = query_last_day(&transport).await;
+        assert_eq!(times.len(), MAX_DAY_SLOTS_PAGES);
+        assert_eq!(offsets, (0..MAX_DAY_SLOTS_PAGES).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn plain_service_unavailable_is_retried() {
+        let office_requests = Arc::new(AtomicUsize::new(0));
+        let transport = {
+            let office_requests = office_requests.clone();
+            FakeTransport::new(move |request| {
+                let mut resp = response(&request.url, OFFICES_PAGE);
+                if request.url.path().ends_with("/oficina.do")
+                    && office_requests.fetch_add(1, Ordering::SeqCst) == 0
+                {
+                    resp.status = reqwest::StatusCode::SERVICE_UNAVAILABLE;
+                    resp.body = "<html><body><h1>503 Service Unavailable</h1></body></html>".into();
+                }
+                resp
+            })
+        };
+        let session = AppointmentSession::builder()
+            .transport(transport)
+            .retry_policy(RetryPolicy {
+                initial_backoff: std::time::Duration::from_millis(1),
+                ..Default::default()
             })
-            .collect())
+            .build()
+            .unwrap();
+
+        let offices = session.list_offices().await.unwrap();
+        assert_eq!(offices.len(), 4);
+        assert_eq!(office_requests.load(Ordering::SeqCst), 2);
     }
 
-    pub async fn get_office_details(
-        &self,
-        office_id: OfficeId,
-    ) -> anyhow::Result<Option<NetOfficeModel>> {
-        let office_id_str = office_id.0.to_string();
+    #[tokio::test]
+    async fn plain_service_unavailable_on_json_endpoint_is_retried() {
+        let slot_requests = Arc::new(AtomicUsize::new(0));
+        let transport = {
+            let slot_requests = slot_requests.clone();
+            FakeTransport::new(move |request| {
+                if !request.url.path().ends_with("/franjasDia.do") {
+                    return response(&request.url, "");
+                }
+                if slot_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                    let mut resp = response(
+                        &request.url,
+                        "<html><body><h1>503 Service Unavailable</h1></body></html>",
+                    );
+                    resp.status = reqwest::StatusCode::SERVICE_UNAVAILABLE;
+                    resp
+                } else {
+                    response(&request.url, slots_page([Some("09:00")]))
+                }
+            })
+        };
+        let session = AppointmentSession::builder()
+            .transport(transport.clone())
+            .retry_policy(RetryPolicy {
+                initial_backoff: std::time::Duration::from_millis(1),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
 
-        let resp = Self::trace_send_request(
-            self.client
-                .get(ENDPOINT_OFFICE_INFO.clone())
-                .query(&HashMap::from([("idOficina", office_id_str)])),
-        )
-        .await?;
+        let day_slots = session
+            .get_appointment_slots_for_office_day(ProcedureOfficeId(1290), date(2026, 3, 30))
+            .await
+            .unwrap();
+        assert_eq!(day_slots.available_slots().count(), 1);
+        assert_eq!(slot_requests.load(Ordering::SeqCst), 2);
+        // Retried by the retry policy, without initializing the session again.
+        assert_eq!(
+            transport
+                .paths()
+                .iter()
+                .filter(|path| path.ends_with("/AjaxPantallaAcceso"))
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttled_request_waits_as_asked() {
+        let office_requests = Arc::new(AtomicUsize::new(0));
+        let transport = {
+            let office_requests = office_requests.clone();
+            FakeTransport::new(move |request| {
+                let mut resp = response(&request.url, OFFICES_PAGE);
+                if request.url.path().ends_with("/oficina.do")
+                    && office_requests.fetch_add(1, Ordering::SeqCst) == 0
+                {
+                    resp.status = StatusCode::TOO_MANY_REQUESTS;
+                    resp.headers
+                        .insert(reqwest::header::RETRY_AFTER, HeaderValue::from_static("5"));
+                    resp.body = String::new();
+                }
+                resp
+            })
+        };
+        let session = AppointmentSession::builder()
+            .transport(transport)
+            .retry_policy(RetryPolicy {
+                initial_backoff: std::time::Duration::from_millis(1),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        let start = tokio::time::Instant::now();
+        let offices = session.list_offices().await.unwrap();
+        assert_eq!(offices.len(), 4);
+        assert_eq!(office_requests.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() >= std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn expired_session_is_initialized_again() {
+        let office_requests = Arc::new(AtomicUsize::new(0));
+        let transport = {
+            let office_requests = office_requests.clone();
+            FakeTransport::new(move |request| {
+                let base = Url::parse(crate::DEFAULT_BASE_URL).unwrap();
+                if !request.url.path().ends_with("/oficina.do") {
+                    return response(&request.url, "");
+                }
+
+                // The first time, the server redirects to its landing page,
+                // as it does when the session cookies are no longer valid.
+                if office_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                    response(&base, "<html><body>Bienvenido</body></html>")
+                } else {
+                    response(&request.url, OFFICES_PAGE)
+                }
+            })
+        };
+
+        let offices = transport.session().list_offices().await.unwrap();
+        assert_eq!(offices.len(), 4);
+        assert_eq!(
+            transport.paths(),
+            [
+                "/GNSIS_WBCIUDADANO/",
+                "/GNSIS_WBCIUDADANO/AjaxPantallaAcceso",
+                "/GNSIS_WBCIUDADANO/oficina.do",
+                "/GNSIS_WBCIUDADANO/",
+                "/GNSIS_WBCIUDADANO/AjaxPantallaAcceso",
+                "/GNSIS_WBCIUDADANO/oficina.do",
+            ]
+        );
+    }
 
-        let body = Self::trace_body_and_error_for_response("get_office_details", resp).await?;
-        Ok(Self::read_office_optional(&body)?)
+    /// Writes a state file for an initialized session against the given base
+    /// URL, saved long ago.
+    fn write_state_file(dir: &TestDir, base_url: &str) -> PathBuf {
+        let path = dir.join("state.json");
+        let state = serde_json::json!({
+            "saved_at": {"secs_since_epoch": 0, "nanos_since_epoch": 0},
+            "base_url": base_url,
+            "initialized": true,
+            "cookies": "saved cookies",
+        });
+        std::fs::write(&path, state.to_string()).unwrap();
+        path
+    }
+
+    fn session_with_state_file(transport: &FakeTransport, path: &Path) -> AppointmentSession {
+        AppointmentSession::builder()
+            .transport(transport.clone())
+            .retry_policy(RetryPolicy::none())
+            .state_file(path)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn initialized_state_file_skips_initialization() {
+        let dir = TestDir::new("state-file-restored");
+        let path = write_state_file(&dir, crate::DEFAULT_BASE_URL);
+        let transport = FakeTransport::serving("/oficina.do", OFFICES_PAGE);
+
+        let offices = session_with_state_file(&transport, &path)
+            .list_offices()
+            .await
+            .unwrap();
+        assert_eq!(offices.len(), 4);
+        assert_eq!(transport.cookies(), "saved cookies");
+        assert_eq!(transport.paths(), ["/GNSIS_WBCIUDADANO/oficina.do"]);
+    }
+
+    #[tokio::test]
+    async fn rejected_state_file_is_initialized_and_saved_again() {
+        let dir = TestDir::new("state-file-rejected");
+        let path = write_state_file(&dir, crate::DEFAULT_BASE_URL);
+        let office_requests = Arc::new(AtomicUsize::new(0));
+        let transport = {
+            let office_requests = office_requests.clone();
+            FakeTransport::new(move |request| {
+                let base = Url::parse(crate::DEFAULT_BASE_URL).unwrap();
+                if !request.url.path().ends_with("/oficina.do") {
+                    response(&request.url, "")
+                } else if office_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                    response(&base, "<html><body>Bienvenido</body></html>")
+                } else {
+                    response(&request.url, OFFICES_PAGE)
+                }
+            })
+        };
+
+        let offices = session_with_state_file(&transport, &path)
+            .list_offices()
+            .await
+            .unwrap();
+        assert_eq!(offices.len(), 4);
+        assert_eq!(
+            transport.paths(),
+            [
+                "/GNSIS_WBCIUDADANO/oficina.do",
+                "/GNSIS_WBCIUDADANO/",
+                "/GNSIS_WBCIUDADANO/AjaxPantallaAcceso",
+                "/GNSIS_WBCIUDADANO/oficina.do",
+            ]
+        );
+
+        let state: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_ne!(state["saved_at"]["secs_since_epoch"], 0);
+        assert_eq!(state["initialized"], true);
+    }
+
+    #[tokio::test]
+    async fn state_file_of_another_server_is_ignored() {
+        let dir = TestDir::new("state-file-other-server");
+        let path = write_state_file(&dir, "https://example.com/citas/");
+        let transport = FakeTransport::serving("/oficina.do", OFFICES_PAGE);
+
+        let offices = session_with_state_file(&transport, &path)
+            .list_offices()
+            .await
+            .unwrap();
+        assert_eq!(offices.len(), 4);
+        assert_eq!(transport.cookies(), "");
+        assert_eq!(
+            transport.paths(),
+            [
+                "/GNSIS_WBCIUDADANO/",
+                "/GNSIS_WBCIUDADANO/AjaxPantallaAcceso",
+                "/GNSIS_WBCIUDADANO/oficina.do",
+            ]
+        );
     }
 }
@@ -0,0 +1,276 @@
+use chrono::{DateTime, NaiveDate, Timelike};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// The kind of attention of an appointment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AttentionType {
+    /// On-site appointment at the office.
+    #[default]
+    InPerson,
+    /// Appointment by phone.
+    Phone,
+    /// Any other kind of attention, by its numeric code in the page.
+    Other(u32),
+}
+
+impl AttentionType {
+    /// Returns the numeric code used by the page for this attention type.
+    pub fn code(&self) -> u32 {
+        match self {
+            AttentionType::InPerson => 1,
+            AttentionType::Phone => 2,
+            AttentionType::Other(code) => *code,
+        }
+    }
+}
+
+/// Parameters for querying the slots of a day.
+#[derive(Debug, Clone, Default)]
+pub struct DaySlotsQuery {
+    pub attention_type: AttentionType,
+    /// Whether the queried day is the last one with appointments in the
+    /// office, for which the server may split the slots across several pages.
+    pub last_day: bool,
+}
+
+/// A slot of a day, either available or already booked.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    /// Start time of the slot, serialized in RFC 3339 format.
+    #[serde(with = "madrid_datetime")]
+    pub time: DateTime<Tz>,
+    pub available: bool,
+}
+
+/// The slots of a day starting at the same minute.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MinuteSlots {
+    pub slots: Vec<Slot>,
+}
+
+/// The slots of a day within the same hour, as grouped by the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HourlySlots {
+    pub minutes: Vec<MinuteSlots>,
+}
+
+/// All the slots of a day in an office, with their availability.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaySlots {
+    pub day: NaiveDate,
+    pub hours: Vec<HourlySlots>,
+}
+
+/// Occupancy figures of a set of slots.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Occupancy {
+    pub total: usize,
+    pub available: usize,
+}
+
+impl Occupancy {
+    fn of<'a>(slots: impl Iterator<Item = &'a Slot>) -> Self {
+        slots.fold(Occupancy::default(), |acc, slot| Occupancy {
+            total: acc.total + 1,
+            available: acc.available + slot.available as usize,
+        })
+    }
+
+    pub fn booked(&self) -> usize {
+        self.total - self.available
+    }
+
+    /// Returns the fraction of booked slots, between 0 and 1, or `None` if
+    /// there are no slots at all.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.total == 0 {
+            None
+        } else {
+            Some(self.booked() as f64 / self.total as f64)
+        }
+    }
+}
+
+impl HourlySlots {
+    pub fn slots(&self) -> impl Iterator<Item = &Slot> {
+        self.minutes.iter().flat_map(|minute| minute.slots.iter())
+    }
+
+    /// Returns the hour of the day the slots belong to, or `None` if there
+    /// are no slots in this group.
+    pub fn hour(&self) -> Option<u32> {
+        self.slots().next().map(|slot| slot.time.hour())
+    }
+
+    pub fn occupancy(&self) -> Occupancy {
+        Occupancy::of(self.slots())
+    }
+}
+
+impl DaySlots {
+    pub fn slots(&self) -> impl Iterator<Item = &Slot> {
+        self.hours.iter().flat_map(|hour| hour.slots())
+    }
+
+    pub fn available_slots(&self) -> impl Iterator<Item = DateTime<Tz>> {
+        self.slots()
+            .filter(|slot| slot.available)
+            .map(|slot| slot.time)
+    }
+
+    pub fn occupancy(&self) -> Occupancy {
+        Occupancy::of(self.slots())
+    }
+
+    /// Returns the occupancy of each hour of the day that has slots.
+    pub fn hourly_occupancy(&self) -> Vec<(u32, Occupancy)> {
+        self.hours
+            .iter()
+            .filter_map(|hour| Some((hour.hour()?, hour.occupancy())))
+            .collect()
+    }
+}
+
+/// (De)serializes dates and times in Madrid as RFC 3339 strings.
+mod madrid_datetime {
+    use chrono::{DateTime, FixedOffset};
+    use chrono_tz::Tz;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Tz>, D::Error> {
+        let time = DateTime::<FixedOffset>::deserialize(deserializer)?;
+        Ok(time.with_timezone(&chrono_tz::Europe::Madrid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn slot(hour: u32, minute: u32, available: bool) -> Slot {
+        Slot {
+            time: chrono_tz::Europe::Madrid
+                .with_ymd_and_hms(2026, 11, 3, hour, minute, 0)
+                .unwrap(),
+            available,
+        }
+    }
+
+    fn hour(minutes: Vec<Vec<Slot>>) -> HourlySlots {
+        HourlySlots {
+            minutes: minutes
+                .into_iter()
+                .map(|slots| MinuteSlots { slots })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn occupancy_is_computed_per_hour_and_day() {
+        let day = DaySlots {
+            day: NaiveDate::from_ymd_opt(2026, 11, 3).unwrap(),
+            hours: vec![
+                hour(vec![
+                    vec![slot(9, 0, true), slot(9, 0, false)],
+                    vec![slot(9, 30, false)],
+                ]),
+                hour(vec![]),
+                hour(vec![vec![]]),
+                hour(vec![vec![slot(10, 0, true)]]),
+            ],
+        };
+
+        assert_eq!(day.hours[0].hour(), Some(9));
+        assert_eq!(day.hours[1].hour(), None);
+        assert_eq!(day.hours[2].hour(), None);
+        assert_eq!(
+            day.hours[0].occupancy(),
+            Occupancy {
+                total: 3,
+                available: 1
+            }
+        );
+
+        let occupancy = day.occupancy();
+        assert_eq!(
+            occupancy,
+            Occupancy {
+                total: 4,
+                available: 2
+            }
+        );
+        assert_eq!(occupancy.booked(), 2);
+        assert_eq!(occupancy.ratio(), Some(0.5));
+        assert_eq!(day.hours[1].occupancy().ratio(), None);
+
+        assert_eq!(
+            day.hourly_occupancy(),
+            [
+                (
+                    9,
+                    Occupancy {
+                        total: 3,
+                        available: 1
+                    }
+                ),
+                (
+                    10,
+                    Occupancy {
+                        total: 1,
+                        available: 1
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn slots_keep_their_time_and_time_zone_through_json() {
+        // The second occurrence of a time repeated at the end of daylight
+        // saving time, which only its offset tells apart from the first one.
+        let repeated = chrono_tz::Europe::Madrid
+            .with_ymd_and_hms(2026, 10, 25, 2, 30, 0)
+            .latest()
+            .unwrap();
+        let day = crate::DayAvailability {
+            day: NaiveDate::from_ymd_opt(2026, 10, 25).unwrap(),
+            slots: Some(vec![
+                Slot {
+                    time: repeated,
+                    available: true,
+                },
+                slot(9, 0, false),
+            ]),
+        };
+
+        let json = serde_json::to_string(&day).unwrap();
+        assert!(
+            json.contains(r#""time":"2026-10-25T02:30:00+01:00""#),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains(r#""time":"2026-11-03T09:00:00+01:00""#),
+            "{}",
+            json
+        );
+
+        let read: crate::DayAvailability = serde_json::from_str(&json).unwrap();
+        let slots = read.slots.unwrap();
+        assert_eq!(read.day, day.day);
+        assert_eq!(slots, day.slots.unwrap());
+        for slot in &slots {
+            assert_eq!(slot.time.timezone(), chrono_tz::Europe::Madrid);
+        }
+        assert_eq!(slots[0].time.offset().to_string(), "CET");
+    }
+}
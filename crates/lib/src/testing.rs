@@ -0,0 +1,198 @@
+//! Helpers for testing the session offline, against canned responses.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use reqwest::{StatusCode, Url, header::HeaderMap};
+
+use crate::{
+    AppointmentSession, RetryPolicy, Transport, TransportError, TransportRequest, TransportResponse,
+};
+
+type Handler = dyn Fn(&TransportRequest) -> TransportResponse + Send + Sync;
+type Delay = dyn Fn(&TransportRequest) -> Duration + Send + Sync;
+
+/// In-memory [`Transport`] answering every request through a closure, and
+/// keeping all the requests it has received.
+#[derive(Clone)]
+pub(crate) struct FakeTransport {
+    handler: Arc<Handler>,
+    delay: Option<Arc<Delay>>,
+    requests: Arc<Mutex<Vec<TransportRequest>>>,
+    cookies: Arc<Mutex<String>>,
+}
+
+impl FakeTransport {
+    pub(crate) fn new(
+        handler: impl Fn(&TransportRequest) -> TransportResponse + Send + Sync + 'static,
+    ) -> Self {
+        FakeTransport {
+            handler: Arc::new(handler),
+            delay: None,
+            requests: Arc::default(),
+            cookies: Arc::default(),
+        }
+    }
+
+    /// Delays the response to each request by the duration returned by the
+    /// closure, so tests running with a paused clock can control the order in
+    /// which concurrent requests complete.
+    pub(crate) fn with_delay(
+        mut self,
+        delay: impl Fn(&TransportRequest) -> Duration + Send + Sync + 'static,
+    ) -> Self {
+        self.delay = Some(Arc::new(delay));
+        self
+    }
+
+    /// Answers every request with a successful response from the requested
+    /// URL, with the body returned by the closure for its path.
+    pub(crate) fn with_bodies(handler: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        Self::new(move |request| response(&request.url, handler(request.url.path())))
+    }
+
+    /// Answers the requests to the path ending with the given suffix with the
+    /// given body, and every other request with an empty body.
+    pub(crate) fn serving(path_suffix: &'static str, body: impl Into<String>) -> Self {
+        let body = body.into();
+        Self::with_bodies(move |path| {
+            if path.ends_with(path_suffix) {
+                body.clone()
+            } else {
+                String::new()
+            }
+        })
+    }
+
+    /// Returns the paths of all the requests received so far.
+    pub(crate) fn paths(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.url.path().to_string())
+            .collect()
+    }
+
+    /// Returns all the requests received so far.
+    pub(crate) fn requests(&self) -> Vec<TransportRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the cookies last imported into the transport, which it exports
+    /// as they are.
+    pub(crate) fn cookies(&self) -> String {
+        self.cookies.lock().unwrap().clone()
+    }
+
+    /// Builds a session sending its requests through this transport, without
+    /// retrying them.
+    pub(crate) fn session(&self) -> AppointmentSession {
+        AppointmentSession::builder()
+            .transport(self.clone())
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl Transport for FakeTransport {
+    async fn send(&self, request: TransportRequest) -> Result<TransportResponse, TransportError> {
+        if let Some(delay) = &self.delay {
+            tokio::time::sleep(delay(&request)).await;
+        }
+        let response = (self.handler)(&request);
+        self.requests.lock().unwrap().push(request);
+        Ok(response)
+    }
+
+    fn export_cookies(&self) -> Result<Option<String>, TransportError> {
+        Ok(Some(self.cookies()))
+    }
+
+    fn import_cookies(&self, cookies: &str) -> Result<bool, TransportError> {
+        *self.cookies.lock().unwrap() = cookies.to_string();
+        Ok(true)
+    }
+}
+
+/// A successful response served from the given URL.
+pub(crate) fn response(url: &Url, body: impl Into<String>) -> TransportResponse {
+    TransportResponse {
+        status: StatusCode::OK,
+        url: url.clone(),
+        headers: HeaderMap::new(),
+        body: body.into(),
+    }
+}
+
+/// Returns the value of a parameter of the request, either from its query or
+/// from its form encoded body.
+pub(crate) fn param(request: &TransportRequest, name: &str) -> Option<String> {
+    let body = request.body.as_deref().unwrap_or_default();
+    request
+        .url
+        .query_pairs()
+        .chain(url::form_urlencoded::parse(body))
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Body of the slots of a day, as served by `franjasDia.do`: one hourly group
+/// for each of the given times, with a single available slot at that time,
+/// or without any slot if none.
+pub(crate) fn slots_page<S: AsRef<str>>(hours: impl IntoIterator<Item = Option<S>>) -> String {
+    let hours: Vec<_> = hours
+        .into_iter()
+        .map(|time| match time {
+            Some(time) => format!(
+                r#"{{"franjasMinuto": [{{"huecos": [{{"hora": "{}", "disponible": true}}]}}]}}"#,
+                time.as_ref()
+            ),
+            None => r#"{"franjasMinuto": []}"#.to_string(),
+        })
+        .collect();
+    format!("[{}]", hours.join(","))
+}
+
+/// An empty temporary directory for a test, removed along with its contents
+/// when dropped, even if the test fails.
+pub(crate) struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// Creates the directory for the test with the given name, which has to
+    /// be unique among the tests of the crate.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "madrid-cita-previa-test-{}-{}",
+            process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
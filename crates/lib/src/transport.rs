@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{
+    ClientBuilder, Method, StatusCode, Url,
+    header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue},
+};
+use reqwest_cookie_store::CookieStoreMutex;
+use serde::Serialize;
+
+/// Error returned by a [`Transport`] when a request couldn't be completed.
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// An HTTP request to be sent through a [`Transport`].
+#[derive(Debug, Clone)]
+pub struct TransportRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Option<Vec<u8>>,
+}
+
+/// The response to a [`TransportRequest`], with its body already read.
+#[derive(Debug, Clone)]
+pub struct TransportResponse {
+    pub status: StatusCode,
+    /// The final URL of the response, after following any redirect.
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+/// Sends the HTTP requests performed by an
+/// [`AppointmentSession`](crate::AppointmentSession).
+///
+/// Implementations are expected to keep the cookies set by the server between
+/// requests, since the session relies on them for staying authenticated.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, request: TransportRequest) -> Result<TransportResponse, TransportError>;
+
+    /// Serializes all the cookies kept by the transport, including the ones
+    /// only meant to last for the browser session, so they can be restored
+    /// later through [`Transport::import_cookies`]. Returns `None` if the
+    /// transport doesn't support it.
+    fn export_cookies(&self) -> Result<Option<String>, TransportError> {
+        Ok(None)
+    }
+
+    /// Replaces the cookies kept by the transport with the ones serialized by
+    /// [`Transport::export_cookies`]. Returns whether the transport supports
+    /// it.
+    fn import_cookies(&self, _cookies: &str) -> Result<bool, TransportError> {
+        Ok(false)
+    }
+}
+
+impl TransportRequest {
+    pub fn new(method: Method, url: Url) -> Self {
+        TransportRequest {
+            method,
+            url,
+            headers: HeaderMap::new(),
+            body: None,
+        }
+    }
+
+    pub fn get(url: Url) -> Self {
+        Self::new(Method::GET, url)
+    }
+
+    pub fn post(url: Url) -> Self {
+        Self::new(Method::POST, url)
+    }
+
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Appends the given pairs to the query string of the request URL.
+    pub fn query<K: AsRef<str>, V: AsRef<str>>(mut self, pairs: &[(K, V)]) -> Self {
+        self.url.query_pairs_mut().extend_pairs(pairs);
+        self
+    }
+
+    /// Sets the body of the request to the given form, url-encoded.
+    pub fn form<T: Serialize + ?Sized>(mut self, form: &T) -> Self {
+        let body = serde_urlencoded::to_string(form).expect("Form must be url-encodable");
+        self.headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        self.body = Some(body.into_bytes());
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+}
+
+/// Default [`Transport`], backed by a [`reqwest::Client`] with a cookie store.
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+    cookies: Arc<CookieStoreMutex>,
+}
+
+impl ReqwestTransport {
+    pub fn new(cb: ClientBuilder) -> reqwest::Result<Self> {
+        let cookies = Arc::new(CookieStoreMutex::default());
+        Ok(ReqwestTransport {
+            client: cb.cookie_provider(cookies.clone()).build()?,
+            cookies,
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn send(&self, request: TransportRequest) -> Result<TransportResponse, TransportError> {
+        let mut builder = self
+            .client
+            .request(request.method, request.url)
+            .headers(request.headers);
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let resp = builder.send().await?;
+        let status = resp.status();
+        let url = resp.url().clone();
+        let headers = resp.headers().clone();
+        let body = resp.text().await?;
+
+        Ok(TransportResponse {
+            status,
+            url,
+            headers,
+            body,
+        })
+    }
+
+    fn export_cookies(&self) -> Result<Option<String>, TransportError> {
+        let store = self.cookies.lock().unwrap();
+        let mut data = Vec::new();
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(&store, &mut data)?;
+        Ok(Some(String::from_utf8(data)?))
+    }
+
+    fn import_cookies(&self, cookies: &str) -> Result<bool, TransportError> {
+        let loaded = cookie_store::serde::json::load_all(cookies.as_bytes())?;
+        *self.cookies.lock().unwrap() = loaded;
+        Ok(true)
+    }
+}